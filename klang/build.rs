@@ -10,6 +10,44 @@ fn main() {
     config.retain_enum_prefix();
     config.enable_type_names();
 
+    // The AST doubles as the schema for the JSON and YAML program formats.
+    config.message_attribute(
+        ".ast",
+        "#[derive(serde::Serialize, serde::Deserialize)] #[serde(default)]",
+    );
+    config.enum_attribute(
+        ".ast",
+        "#[derive(serde::Serialize, serde::Deserialize)] #[serde(rename_all = \"snake_case\")]",
+    );
+    config.field_attribute(
+        ".ast.Program.shared",
+        "#[serde(skip_serializing_if = \"Vec::is_empty\")]",
+    );
+    config.field_attribute(
+        ".ast.Command.children",
+        "#[serde(skip_serializing_if = \"Vec::is_empty\")]",
+    );
+    config.field_attribute(
+        ".ast.Command.shared_index",
+        "#[serde(skip_serializing_if = \"Option::is_none\")]",
+    );
+    config.field_attribute(
+        ".ast.Command.location",
+        "#[serde(skip_serializing_if = \"Option::is_none\")]",
+    );
+    config.field_attribute(
+        ".ast.Command.function",
+        "#[serde(skip_serializing_if = \"String::is_empty\")]",
+    );
+    config.field_attribute(
+        ".ast.Command.arguments",
+        "#[serde(skip_serializing_if = \"Vec::is_empty\")]",
+    );
+    config.field_attribute(
+        ".ast.Command.action",
+        "#[serde(skip_serializing_if = \"Option::is_none\")]",
+    );
+
     config
         .compile_protos(
             &["src/proto/ast.proto", "src/proto/ir.proto"],
@@ -0,0 +1,469 @@
+use clap::Parser;
+use klang::parser::analysis::{Analysis, Diagnostic};
+use klang::parser::cst::Span;
+use klang::parser::errors::ParseError;
+use klang::parser::rename::RenameTarget;
+use klang::parser::robot::RobotDescription;
+use klang::parser::schema::PrimitiveSchema;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as NotificationTrait, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, PrepareRenameRequest,
+    References, Rename, Request as RequestTrait,
+};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, Diagnostic as LspDiagnostic,
+    DiagnosticSeverity, DocumentSymbol, DocumentSymbolResponse, Documentation,
+    GotoDefinitionResponse, Hover, HoverContents, HoverProviderCapability, InsertTextFormat,
+    Location, MarkupContent, MarkupKind, OneOf, Position, PrepareRenameResponse,
+    PublishDiagnosticsParams, Range, RenameOptions, ServerCapabilities, SymbolKind,
+    TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url, WorkspaceEdit,
+};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(
+    name = "klang-lsp",
+    version,
+    about = "Language server for klang programs"
+)]
+struct Cli {
+    /// Primitive schema that every command must match
+    #[arg(long)]
+    schema: Option<PathBuf>,
+
+    /// Robot description whose joint limits every command must respect.
+    /// Needs a schema, as only commands lowered into actions can be checked
+    #[arg(long, requires = "schema")]
+    robot: Option<PathBuf>,
+
+    /// Talk over stdin and stdout, which is the only transport
+    #[arg(long, hide = true)]
+    stdio: bool,
+}
+
+struct Document {
+    source: String,
+    analysis: Analysis,
+}
+
+struct Server {
+    connection: Connection,
+    documents: HashMap<Url, Document>,
+    schema: Option<PrimitiveSchema>,
+    robot: Option<RobotDescription>,
+}
+
+/// The LSP position of a byte offset, counting columns in UTF-16 units.
+fn position(source: &str, offset: usize) -> Position {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position {
+        line: before.matches('\n').count() as u32,
+        character: before[line_start..].encode_utf16().count() as u32,
+    }
+}
+
+fn offset(source: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match source[line_start..].find('\n') {
+            Some(i) => line_start += i + 1,
+            None => return source.len(),
+        }
+    }
+    let mut units = 0;
+    for (i, c) in source[line_start..].char_indices() {
+        if units >= position.character as usize || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    source.len()
+}
+
+fn range(source: &str, span: Span) -> Range {
+    Range {
+        start: position(source, span.start),
+        end: position(source, span.end),
+    }
+}
+
+/// Diagnostics cover the rest of the line they were reported at.
+fn diagnostic(source: &str, diagnostic: &Diagnostic) -> LspDiagnostic {
+    let line = source
+        .split('\n')
+        .nth(diagnostic.location.line - 1)
+        .unwrap_or_default()
+        .trim_end_matches('\r');
+    let start: usize = line
+        .chars()
+        .take(diagnostic.location.column - 1)
+        .map(char::len_utf16)
+        .sum();
+    let line_number = diagnostic.location.line as u32 - 1;
+    LspDiagnostic {
+        range: Range {
+            start: Position::new(line_number, start as u32),
+            end: Position::new(line_number, line.encode_utf16().count() as u32),
+        },
+        severity: Some(DiagnosticSeverity::ERROR),
+        source: Some("klang".to_string()),
+        message: diagnostic.message.clone(),
+        ..Default::default()
+    }
+}
+
+fn document_symbols(document: &Document, parent: Option<usize>) -> Vec<DocumentSymbol> {
+    let definitions = &document.analysis.definitions;
+    (0..definitions.len())
+        .filter(|i| definitions[*i].parent == parent)
+        .map(|i| {
+            let definition = &definitions[i];
+            #[allow(deprecated)]
+            DocumentSymbol {
+                name: definition.signature.clone(),
+                detail: None,
+                kind: SymbolKind::FUNCTION,
+                tags: None,
+                deprecated: None,
+                range: range(&document.source, definition.span),
+                selection_range: range(&document.source, definition.name_span),
+                children: Some(document_symbols(document, Some(i))),
+            }
+        })
+        .collect()
+}
+
+impl Server {
+    fn run(&mut self) -> Result<(), Box<dyn Error + Sync + Send>> {
+        while let Ok(message) = self.connection.receiver.recv() {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    let response = self.handle_request(request);
+                    self.connection.sender.send(Message::Response(response))?;
+                }
+                Message::Notification(notification) => self.handle_notification(notification)?,
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_notification(
+        &mut self,
+        notification: Notification,
+    ) -> Result<(), Box<dyn Error + Sync + Send>> {
+        let uri = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: <DidOpenTextDocument as NotificationTrait>::Params =
+                    serde_json::from_value(notification.params)?;
+                self.update(params.text_document.uri.clone(), params.text_document.text);
+                params.text_document.uri
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: <DidChangeTextDocument as NotificationTrait>::Params =
+                    serde_json::from_value(notification.params)?;
+                // The server asks for full sync, so the last change is the
+                // whole document.
+                let Some(change) = params.content_changes.into_iter().last() else {
+                    return Ok(());
+                };
+                self.update(params.text_document.uri.clone(), change.text);
+                params.text_document.uri
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: <DidCloseTextDocument as NotificationTrait>::Params =
+                    serde_json::from_value(notification.params)?;
+                self.documents.remove(&params.text_document.uri);
+                params.text_document.uri
+            }
+            _ => return Ok(()),
+        };
+        let diagnostics = match self.documents.get(&uri) {
+            Some(document) => document
+                .analysis
+                .diagnostics
+                .iter()
+                .map(|d| diagnostic(&document.source, d))
+                .collect(),
+            None => Vec::new(),
+        };
+        let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+        self.connection
+            .sender
+            .send(Message::Notification(Notification::new(
+                PublishDiagnostics::METHOD.to_string(),
+                params,
+            )))?;
+        Ok(())
+    }
+
+    fn update(&mut self, uri: Url, source: String) {
+        let analysis = Analysis::with_checks(&source, self.schema.as_ref(), self.robot.as_ref());
+        self.documents.insert(uri, Document { source, analysis });
+    }
+
+    fn handle_request(&self, request: Request) -> Response {
+        let id = request.id.clone();
+        let result = match request.method.as_str() {
+            GotoDefinition::METHOD => self.params(request).map(|params| {
+                let params: <GotoDefinition as RequestTrait>::Params = params;
+                let position = params.text_document_position_params;
+                self.goto_definition(&position.text_document.uri, position.position)
+                    .map(GotoDefinitionResponse::Scalar)
+                    .map_or(serde_json::Value::Null, |response| {
+                        serde_json::to_value(response).unwrap()
+                    })
+            }),
+            HoverRequest::METHOD => self.params(request).map(|params| {
+                let params: <HoverRequest as RequestTrait>::Params = params;
+                let position = params.text_document_position_params;
+                serde_json::to_value(self.hover(&position.text_document.uri, position.position))
+                    .unwrap()
+            }),
+            References::METHOD => self.params(request).map(|params| {
+                let params: <References as RequestTrait>::Params = params;
+                let position = params.text_document_position;
+                serde_json::to_value(self.references(
+                    &position.text_document.uri,
+                    position.position,
+                    params.context.include_declaration,
+                ))
+                .unwrap()
+            }),
+            DocumentSymbolRequest::METHOD => self.params(request).map(|params| {
+                let params: <DocumentSymbolRequest as RequestTrait>::Params = params;
+                let symbols = self
+                    .documents
+                    .get(&params.text_document.uri)
+                    .map(|document| {
+                        DocumentSymbolResponse::Nested(document_symbols(document, None))
+                    });
+                serde_json::to_value(symbols).unwrap()
+            }),
+            Completion::METHOD => self.params(request).map(|params| {
+                let params: <Completion as RequestTrait>::Params = params;
+                serde_json::to_value(
+                    self.completions(&params.text_document_position.text_document.uri),
+                )
+                .unwrap()
+            }),
+            PrepareRenameRequest::METHOD => self.params(request).map(|params| {
+                let params: <PrepareRenameRequest as RequestTrait>::Params = params;
+                serde_json::to_value(
+                    self.prepare_rename(&params.text_document.uri, params.position),
+                )
+                .unwrap()
+            }),
+            Rename::METHOD => {
+                let params: <Rename as RequestTrait>::Params = match self.params(request) {
+                    Ok(params) => params,
+                    Err(response) => return response,
+                };
+                let position = params.text_document_position;
+                match self.rename(
+                    &position.text_document.uri,
+                    position.position,
+                    &params.new_name,
+                ) {
+                    Ok(edit) => Ok(serde_json::to_value(edit).unwrap()),
+                    Err(e) => Err(Response::new_err(
+                        id.clone(),
+                        ErrorCode::RequestFailed as i32,
+                        e.to_string(),
+                    )),
+                }
+            }
+            method => Err(Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("Unsupported request: {}", method),
+            )),
+        };
+        match result {
+            Ok(value) => Response {
+                id,
+                result: Some(value),
+                error: None,
+            },
+            Err(response) => response,
+        }
+    }
+
+    fn params<P: serde::de::DeserializeOwned>(&self, request: Request) -> Result<P, Response> {
+        serde_json::from_value(request.params).map_err(|e| {
+            Response::new_err(request.id, ErrorCode::InvalidParams as i32, e.to_string())
+        })
+    }
+
+    fn lookup(&self, uri: &Url, position: Position) -> Option<(&Document, usize)> {
+        let document = self.documents.get(uri)?;
+        Some((document, offset(&document.source, position)))
+    }
+
+    fn goto_definition(&self, uri: &Url, position: Position) -> Option<Location> {
+        let (document, offset) = self.lookup(uri, position)?;
+        let definition = document.analysis.call_at(offset)?.definition?;
+        let span = document.analysis.definitions[definition].name_span;
+        Some(Location::new(uri.clone(), range(&document.source, span)))
+    }
+
+    fn hover(&self, uri: &Url, position: Position) -> Option<Hover> {
+        let (document, offset) = self.lookup(uri, position)?;
+        let analysis = &document.analysis;
+        let definition = &analysis.definitions[analysis.definition_at(offset)?];
+        let span = match analysis.call_at(offset) {
+            Some(call) => call.name_span,
+            None => definition.name_span,
+        };
+        let mut value = format!("```klang\n> {}\n```", definition.signature);
+        if !definition.doc.is_empty() {
+            value.push_str(&format!("\n\n{}", definition.doc));
+        }
+        if let Some(expansion) = analysis.expansion_at(offset) {
+            value.push_str(&format!("\n\nExpands to:\n```\n{}```", expansion));
+        }
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(range(&document.source, span)),
+        })
+    }
+
+    fn references(
+        &self,
+        uri: &Url,
+        position: Position,
+        include_declaration: bool,
+    ) -> Option<Vec<Location>> {
+        let (document, offset) = self.lookup(uri, position)?;
+        let analysis = &document.analysis;
+        let definition = analysis.definition_at(offset)?;
+        let mut spans = Vec::new();
+        if include_declaration {
+            spans.push(analysis.definitions[definition].name_span);
+        }
+        spans.extend(analysis.references(definition).map(|call| call.name_span));
+        Some(
+            spans
+                .into_iter()
+                .map(|span| Location::new(uri.clone(), range(&document.source, span)))
+                .collect(),
+        )
+    }
+
+    fn prepare_rename(&self, uri: &Url, position: Position) -> Option<PrepareRenameResponse> {
+        let (document, offset) = self.lookup(uri, position)?;
+        let analysis = &document.analysis;
+        let (target, span) = analysis.rename_target(offset)?;
+        // Functions are renamed by editing their whole signature.
+        let placeholder = match target {
+            RenameTarget::Function(definition) => {
+                analysis.definitions[definition].signature.clone()
+            }
+            RenameTarget::Parameter { name, .. } => name,
+        };
+        Some(PrepareRenameResponse::RangeWithPlaceholder {
+            range: range(&document.source, span),
+            placeholder,
+        })
+    }
+
+    fn rename(
+        &self,
+        uri: &Url,
+        position: Position,
+        new_name: &str,
+    ) -> Result<WorkspaceEdit, ParseError> {
+        let (document, offset) = self
+            .lookup(uri, position)
+            .ok_or_else(|| ParseError::new(format!("Unknown document: {}", uri)))?;
+        let edits = document
+            .analysis
+            .rename_at(offset, new_name)?
+            .into_iter()
+            .map(|edit| TextEdit::new(range(&document.source, edit.span), edit.text))
+            .collect();
+        Ok(WorkspaceEdit::new(HashMap::from([(uri.clone(), edits)])))
+    }
+
+    fn completions(&self, uri: &Url) -> Option<Vec<CompletionItem>> {
+        let document = self.documents.get(uri)?;
+        Some(
+            document
+                .analysis
+                .completions()
+                .into_iter()
+                .map(|completion| CompletionItem {
+                    detail: Some(format!("> {}", completion.label)),
+                    documentation: Some(completion.doc)
+                        .filter(|doc| !doc.is_empty())
+                        .map(Documentation::String),
+                    label: completion.label,
+                    kind: Some(CompletionItemKind::FUNCTION),
+                    insert_text: Some(completion.snippet),
+                    insert_text_format: Some(InsertTextFormat::SNIPPET),
+                    ..Default::default()
+                })
+                .collect(),
+        )
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error + Sync + Send>> {
+    let cli = Cli::parse();
+    let schema = cli
+        .schema
+        .as_deref()
+        .map(PrimitiveSchema::load)
+        .transpose()?;
+    let robot = cli
+        .robot
+        .as_deref()
+        .map(RobotDescription::load)
+        .transpose()?;
+    if let (Some(robot), Some(schema)) = (&robot, &schema) {
+        robot.check_schema(schema)?;
+    }
+
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        references_provider: Some(OneOf::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        rename_provider: Some(OneOf::Right(RenameOptions {
+            prepare_provider: Some(true),
+            work_done_progress_options: Default::default(),
+        })),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec!["\"".to_string()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut server = Server {
+        connection,
+        documents: HashMap::new(),
+        schema,
+        robot,
+    };
+    server.run()?;
+    drop(server);
+    io_threads.join()?;
+    Ok(())
+}
@@ -1,27 +1,1153 @@
-use klang::{compile_file, compile_file_inplace};
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use klang::executor::checkpoint::Checkpoint;
+use klang::executor::debugger::{Breakpoint, Debugger, PromptHandler};
+use klang::executor::errors::ExecutionError;
+use klang::executor::sim::SimBackend;
+use klang::executor::{Event, Executor, RunStatus};
+use klang::parser::analysis::Analysis;
+use klang::parser::docs::Reference;
+use klang::parser::errors::ParseError;
+use klang::parser::estimate::DurationModel;
+use klang::parser::rename::apply_edits;
+use klang::parser::robot::RobotDescription;
+use klang::parser::schema::PrimitiveSchema;
+use klang::parser::structs::KlangProgram;
+use klang::parser::{
+    call_graph_from_file, dump_ir_file, format_string, parse_file_with_schema, tests_from_file,
+    write_program_to_file, OutputFormat,
+};
+use klang::project::{BuildStatus, Project, MANIFEST_FILE};
+use klang::repl::{is_incomplete, Session};
+use klang::testing::{has_test_blocks, run_test, GoldenTest};
+use klang::watch::Watcher;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
 use std::env;
-use std::path::Path; // Import from the library
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    match args.len() {
-        2 => match compile_file_inplace(Path::new(&args[1]), false) {
-            Ok(_) => (),
+const EXIT_CODES: &str = "Exit codes:
+  0  Success
+  1  A program failed to compile or check, or is not formatted
+  2  Invalid command line arguments
+  3  A program failed while running";
+
+#[derive(Parser)]
+#[command(name = "kompile", version, about = "Compiles and runs klang programs", after_help = EXIT_CODES)]
+struct Cli {
+    /// Only print errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print every file and command as it is processed
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct Checks {
+    /// Primitive schema that every command must match
+    #[arg(long)]
+    schema: Option<PathBuf>,
+
+    /// Robot description whose joint limits every command must respect.
+    /// Needs a schema, as only commands lowered into actions can be checked
+    #[arg(long, requires = "schema")]
+    robot: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct Watch {
+    /// Recompile whenever an input, the schema or the robot description
+    /// changes on disk
+    #[arg(long)]
+    watch: bool,
+
+    /// After each successful recompile, run the programs on the simulated robot
+    #[arg(long, requires = "watch")]
+    sim: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compile programs into the given output format, or with no inputs,
+    /// every entry point of the project
+    Build {
+        inputs: Vec<PathBuf>,
+
+        /// Project manifest to build when no inputs are given. Defaults to
+        /// the klang.toml in this directory or the nearest one above it.
+        #[arg(long, conflicts_with = "inputs")]
+        manifest: Option<PathBuf>,
+
+        /// Output file, or output directory when building several inputs or
+        /// a project. Defaults to the input with the extension of the format:
+        /// `.ko`, `.txt`, `.json` or `.yaml`.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Output format: binary, text, json or yaml
+        #[arg(short, long, default_value = "binary", value_parser = parse_format)]
+        format: OutputFormat,
+
+        /// Share repeated command subtrees in the output, which must be in
+        /// the binary format
+        #[arg(short, long)]
+        deduplicate: bool,
+
+        #[command(flatten)]
+        checks: Checks,
+
+        #[command(flatten)]
+        watch: Watch,
+    },
+    /// Check programs for errors without writing any output
+    Check {
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        #[command(flatten)]
+        checks: Checks,
+
+        #[command(flatten)]
+        watch: Watch,
+    },
+    /// Rewrite source files in canonical form
+    Fmt {
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Report files that are not formatted instead of rewriting them
+        #[arg(long)]
+        check: bool,
+    },
+    /// Rename a function and every call to it, or a parameter of a function
+    Rename {
+        input: PathBuf,
+
+        /// Signature of the function, or with --in, name of the parameter
+        from: String,
+
+        /// New signature of the function, or new name of the parameter
+        to: String,
+
+        /// Signature of the function whose parameter is renamed
+        #[arg(long = "in", value_name = "SIGNATURE")]
+        function: Option<String>,
+    },
+    /// Run a program on the simulated robot
+    Run {
+        /// A source file, or a compiled program in binary, JSON or YAML
+        input: PathBuf,
+
+        #[command(flatten)]
+        checks: Checks,
+
+        /// Save a trace of every executed command as JSON lines
+        #[arg(long)]
+        trace: Option<PathBuf>,
+
+        /// Save a checkpoint after every completed command
+        #[arg(long)]
+        checkpoint: Option<PathBuf>,
+
+        /// Continue from the checkpoint instead of starting over
+        #[arg(long, requires = "checkpoint")]
+        resume: bool,
+
+        /// Step through the program in an interactive debugger
+        #[arg(long)]
+        debug: bool,
+
+        /// Pause in the debugger at a line number or function signature
+        #[arg(long = "break", requires = "debug")]
+        breakpoints: Vec<String>,
+    },
+    /// Compare the expanded programs of source files with golden files, and
+    /// run their test blocks on the simulated robot
+    Test {
+        /// Source files, or directories to search for source files that have
+        /// test blocks or a `.expected` or `.expected.json` file next to them
+        #[arg(default_value = ".")]
+        paths: Vec<PathBuf>,
+
+        #[command(flatten)]
+        checks: Checks,
+
+        /// Write the expanded programs to the golden files instead of
+        /// comparing them
+        #[arg(long)]
+        bless: bool,
+    },
+    /// Define functions and try calls interactively
+    Repl {
+        #[command(flatten)]
+        checks: Checks,
+
+        /// Run calls on the simulated robot instead of showing their expansion
+        #[arg(long)]
+        sim: bool,
+    },
+    /// Print an intermediate form or visualization of a program
+    Dump {
+        #[arg(value_enum)]
+        kind: DumpKind,
+
+        input: PathBuf,
+
+        /// Rendering of the call graph or tree: dot, mermaid or html
+        #[arg(long, value_enum, default_value = "dot")]
+        style: Style,
+
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Primitive schema used to lower commands in the AST
+        #[arg(long)]
+        schema: Option<PathBuf>,
+    },
+    /// Reconstruct source code from a compiled program
+    Decompile {
+        /// A compiled program in binary, JSON or YAML
+        input: PathBuf,
+
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Generate reference documentation for the functions in source files
+    Docs {
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        #[arg(long, value_enum, default_value = "markdown")]
+        format: DocsFormat,
+
+        /// Title of the page
+        #[arg(long, default_value = "Klang functions")]
+        title: String,
+
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Estimate how long a program takes to run
+    Estimate {
+        input: PathBuf,
+
+        /// Primitive schema giving the duration of each primitive
+        #[arg(long)]
+        schema: Option<PathBuf>,
+
+        /// Table of durations by primitive name or command text
+        #[arg(long)]
+        durations: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DumpKind {
+    /// The parsed program before functions are expanded
+    Ir,
+    /// The expanded program as JSON
+    Ast,
+    /// The calls between functions
+    CallGraph,
+    /// The expanded program tree
+    Tree,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DocsFormat {
+    Markdown,
+    Html,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Style {
+    Dot,
+    Mermaid,
+    Html,
+}
+
+fn parse_format(format: &str) -> Result<OutputFormat, ParseError> {
+    format.parse()
+}
+
+struct Reporter {
+    quiet: bool,
+    verbose: bool,
+}
+
+impl Reporter {
+    fn info(&self, message: impl std::fmt::Display) {
+        if !self.quiet {
+            println!("{}", message);
+        }
+    }
+
+    fn detail(&self, message: impl std::fmt::Display) {
+        if self.verbose {
+            println!("{}", message);
+        }
+    }
+
+    fn error(&self, path: &Path, error: impl std::fmt::Display) {
+        eprintln!("error: {}: {}", path.display(), error);
+    }
+
+    fn failure(&self, error: impl std::fmt::Display) {
+        eprintln!("error: {}", error);
+    }
+}
+
+fn load_schema(schema: Option<&Path>) -> Result<Option<PrimitiveSchema>, ParseError> {
+    schema.map(PrimitiveSchema::load).transpose()
+}
+
+fn load_robot(
+    robot: Option<&Path>,
+    schema: Option<&PrimitiveSchema>,
+) -> Result<Option<RobotDescription>, ParseError> {
+    let robot = robot.map(RobotDescription::load).transpose()?;
+    if let (Some(robot), Some(schema)) = (&robot, schema) {
+        robot.check_schema(schema)?;
+    }
+    Ok(robot)
+}
+
+/// Loads a `.k` source file, or a compiled program picked by extension.
+fn load_program(
+    input: &Path,
+    schema: Option<&PrimitiveSchema>,
+) -> Result<KlangProgram, ParseError> {
+    match input.extension().and_then(|extension| extension.to_str()) {
+        Some("k") => parse_file_with_schema(input, schema),
+        Some("json") => KlangProgram::load_json(input),
+        Some("yaml") | Some("yml") => KlangProgram::load_yaml(input),
+        _ => KlangProgram::load_binary(input),
+    }
+}
+
+fn write_output(output: Option<&Path>, rendered: &str) -> Result<(), Box<dyn Error>> {
+    match output {
+        Some(output) => fs::write(output, rendered)?,
+        None => print!("{}", rendered),
+    }
+    Ok(())
+}
+
+/// Compiles and checks one source file.
+fn compile(
+    input: &Path,
+    schema: Option<&PrimitiveSchema>,
+    robot: Option<&RobotDescription>,
+) -> Result<KlangProgram, ParseError> {
+    let program = parse_file_with_schema(input, schema)?;
+    if let Some(robot) = robot {
+        program.check_safety(robot)?;
+    }
+    Ok(program)
+}
+
+fn build(
+    reporter: &Reporter,
+    inputs: &[PathBuf],
+    output: Option<&Path>,
+    format: OutputFormat,
+    deduplicate: bool,
+    checks: &Checks,
+) -> Result<bool, Box<dyn Error>> {
+    let schema = load_schema(checks.schema.as_deref())?;
+    let robot = load_robot(checks.robot.as_deref(), schema.as_ref())?;
+    if inputs.len() > 1 {
+        if let Some(output) = output {
+            fs::create_dir_all(output)?;
+        }
+    }
+
+    let outputs: Vec<PathBuf> = inputs
+        .iter()
+        .map(|input| {
+            let name = input.with_extension(format.extension());
+            match output {
+                Some(output) if inputs.len() == 1 => output.to_path_buf(),
+                Some(output) => output.join(name.file_name().unwrap_or_default()),
+                None => name,
+            }
+        })
+        .collect();
+    for (i, path) in outputs.iter().enumerate() {
+        if let Some(j) = outputs[..i].iter().position(|other| other == path) {
+            return Err(format!(
+                "{} and {} would both be compiled to {}",
+                inputs[j].display(),
+                inputs[i].display(),
+                path.display()
+            )
+            .into());
+        }
+    }
+
+    let mut failed = 0;
+    for (input, output) in inputs.iter().zip(outputs) {
+        let result = compile(input, schema.as_ref(), robot.as_ref()).and_then(|program| {
+            if deduplicate && format == OutputFormat::Binary {
+                program.save_binary_deduplicated(&output)
+            } else {
+                write_program_to_file(&program, &output, format)
+            }
+        });
+        match result {
+            Ok(()) => reporter.detail(format!(
+                "Compiled {} -> {}",
+                input.display(),
+                output.display()
+            )),
+            Err(e) => {
+                reporter.error(input, e);
+                failed += 1;
+            }
+        }
+    }
+    reporter.info(format!(
+        "Compiled {} of {} files",
+        inputs.len() - failed,
+        inputs.len()
+    ));
+    Ok(failed == 0)
+}
+
+fn check(reporter: &Reporter, inputs: &[PathBuf], checks: &Checks) -> Result<bool, Box<dyn Error>> {
+    let schema = load_schema(checks.schema.as_deref())?;
+    let robot = load_robot(checks.robot.as_deref(), schema.as_ref())?;
+    let mut failed = 0;
+    for input in inputs {
+        match compile(input, schema.as_ref(), robot.as_ref()) {
+            Ok(_) => reporter.detail(format!("{}: ok", input.display())),
             Err(e) => {
-                eprintln!("{}", e);
-                std::process::exit(1);
+                reporter.error(input, e);
+                failed += 1;
+            }
+        }
+    }
+    reporter.info(format!(
+        "Checked {} files, {} with errors",
+        inputs.len(),
+        failed
+    ));
+    Ok(failed == 0)
+}
+
+/// Reruns `step` every time one of the files it reads changes. The files
+/// are listed again after every run, as a run may start reading new ones.
+/// Errors are reported and watching continues until the process is
+/// interrupted.
+fn watch(
+    reporter: &Reporter,
+    files: impl Fn() -> Result<Vec<PathBuf>, Box<dyn Error>>,
+    mut step: impl FnMut() -> Result<bool, Box<dyn Error>>,
+) -> Result<bool, Box<dyn Error>> {
+    // Stamp the files before each step, so that saves made while it runs
+    // are picked up by the next wait.
+    let mut watcher = Watcher::new(files().unwrap_or_else(|e| {
+        reporter.failure(e);
+        Vec::new()
+    }));
+    loop {
+        if let Err(e) = step() {
+            reporter.failure(e);
+        }
+        // Keep watching the same files if they cannot be listed.
+        match files() {
+            Ok(files) => watcher.set_paths(files),
+            Err(e) => reporter.failure(e),
+        }
+        reporter.info(format!(
+            "Watching {} files for changes",
+            watcher.paths().count()
+        ));
+        for path in watcher.wait() {
+            reporter.info(format!("\n{} changed", path.display()));
+        }
+    }
+}
+
+/// The source files and the schema and robot description they are checked
+/// against.
+fn watched_files(inputs: &[PathBuf], checks: &Checks) -> Vec<PathBuf> {
+    inputs
+        .iter()
+        .chain(&checks.schema)
+        .chain(&checks.robot)
+        .cloned()
+        .collect()
+}
+
+/// Runs each program on a fresh simulated robot and reports how it went.
+fn simulate(
+    reporter: &Reporter,
+    programs: impl IntoIterator<Item = (PathBuf, Result<KlangProgram, ParseError>)>,
+    robot: Option<&RobotDescription>,
+) {
+    for (input, program) in programs {
+        let mut executor = Executor::new(SimBackend::default());
+        if let Some(robot) = robot {
+            executor.set_robot(robot.clone());
+        }
+        let result = program
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|program| Ok(executor.run(&program)?));
+        match result {
+            Ok(summary) => reporter.info(format!(
+                "{}: finished after {} commands in {:.1}s",
+                input.display(),
+                summary.commands,
+                executor.backend().elapsed()
+            )),
+            Err(e) => reporter.error(&input, e),
+        }
+    }
+}
+
+fn simulate_files(
+    reporter: &Reporter,
+    inputs: &[PathBuf],
+    checks: &Checks,
+) -> Result<(), Box<dyn Error>> {
+    let schema = load_schema(checks.schema.as_deref())?;
+    let robot = load_robot(checks.robot.as_deref(), schema.as_ref())?;
+    let programs = inputs.iter().map(|input| {
+        (
+            input.clone(),
+            compile(input, schema.as_ref(), robot.as_ref()),
+        )
+    });
+    simulate(reporter, programs, robot.as_ref());
+    Ok(())
+}
+
+/// Builds the project described by a manifest, by default the `klang.toml`
+/// in the current directory or the nearest one above it.
+#[allow(clippy::too_many_arguments)]
+fn build_project(
+    reporter: &Reporter,
+    manifest: Option<&Path>,
+    output: Option<&Path>,
+    format: OutputFormat,
+    deduplicate: bool,
+    checks: &Checks,
+    options: &Watch,
+) -> Result<bool, Box<dyn Error>> {
+    // Loaded again for every build when watching, as the manifest may change.
+    let load = || -> Result<Project, Box<dyn Error>> {
+        let mut project = match manifest {
+            Some(manifest) => Project::load(manifest)?,
+            None => Project::find(Path::new(""))?.ok_or_else(|| {
+                format!(
+                    "No input files given and no {} found in this directory or above it",
+                    MANIFEST_FILE
+                )
+            })?,
+        };
+        // Paths given on the command line are relative to the current
+        // directory, which the project resolves absolute paths against.
+        if let Some(output) = output {
+            project.manifest.out_dir = std::path::absolute(output)?;
+        }
+        if let Some(schema) = &checks.schema {
+            project.manifest.schema = Some(std::path::absolute(schema)?);
+        }
+        if let Some(robot) = &checks.robot {
+            project.manifest.robot = Some(std::path::absolute(robot)?);
+        }
+        Ok(project)
+    };
+
+    let step = || -> Result<bool, Box<dyn Error>> {
+        let project = load()?;
+        let results = project.build(format, deduplicate)?;
+        let mut compiled = 0;
+        let mut up_to_date = 0;
+        for (entry, status) in &results {
+            match status {
+                BuildStatus::Compiled => {
+                    compiled += 1;
+                    reporter.detail(format!(
+                        "Compiled {} -> {}",
+                        entry.display(),
+                        project.output_path(entry, format).display()
+                    ));
+                }
+                BuildStatus::UpToDate => {
+                    up_to_date += 1;
+                    reporter.detail(format!("{} is up to date", entry.display()));
+                }
+                BuildStatus::Failed(e) => reporter.error(entry, e),
             }
-        },
-        3 => match compile_file(Path::new(&args[1]), Path::new(&args[2]), false) {
-            Ok(_) => (),
+        }
+        reporter.info(format!(
+            "Compiled {} of {} entry points, {} up to date",
+            compiled,
+            results.len() - up_to_date,
+            up_to_date
+        ));
+        let succeeded = compiled + up_to_date == results.len();
+        if succeeded && options.sim {
+            let schema = project.load_schema()?;
+            let robot = project.load_robot(schema.as_ref())?;
+            let programs = project.entry_points().into_iter().map(|entry| {
+                let program = project.compile(&entry, schema.as_ref(), robot.as_ref());
+                (entry, program)
+            });
+            simulate(reporter, programs, robot.as_ref());
+        }
+        Ok(succeeded)
+    };
+    if options.watch {
+        watch(reporter, || Ok(load()?.files()?), step)
+    } else {
+        step()
+    }
+}
+
+fn format_files(
+    reporter: &Reporter,
+    inputs: &[PathBuf],
+    check: bool,
+) -> Result<bool, Box<dyn Error>> {
+    let mut failed = 0;
+    let mut unformatted = 0;
+    for input in inputs {
+        let result = fs::read_to_string(input)
+            .map_err(ParseError::from)
+            .and_then(|source| Ok((format_string(&source)?, source)));
+        let (formatted, source) = match result {
+            Ok(result) => result,
             Err(e) => {
-                eprintln!("{}", e);
-                std::process::exit(1);
+                reporter.error(input, e);
+                failed += 1;
+                continue;
+            }
+        };
+        if formatted == source {
+            reporter.detail(format!("{} is formatted", input.display()));
+        } else if check {
+            reporter.info(format!("{} is not formatted", input.display()));
+            unformatted += 1;
+        } else {
+            fs::write(input, formatted)?;
+            reporter.detail(format!("Formatted {}", input.display()));
+        }
+    }
+    Ok(failed == 0 && unformatted == 0)
+}
+
+fn rename(
+    reporter: &Reporter,
+    input: &Path,
+    from: &str,
+    to: &str,
+    function: Option<&str>,
+) -> Result<bool, Box<dyn Error>> {
+    let source = fs::read_to_string(input)?;
+    let analysis = Analysis::new(&source);
+    if analysis.tree.is_none() {
+        for diagnostic in &analysis.diagnostics {
+            let location = diagnostic.location;
+            let message =
+                ParseError::at(diagnostic.message.clone(), location.line, location.column);
+            reporter.error(input, message);
+        }
+        return Ok(false);
+    }
+    let edits = match function {
+        Some(signature) => {
+            let definition = analysis.find_definition(signature)?;
+            analysis.rename_parameter(definition, from, to)?
+        }
+        None => analysis.rename_function(analysis.find_definition(from)?, to)?,
+    };
+    fs::write(input, apply_edits(&source, &edits))?;
+    reporter.info(format!(
+        "Renamed {} occurrences in {}",
+        edits.len(),
+        input.display()
+    ));
+    Ok(true)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run(
+    reporter: &Reporter,
+    input: &Path,
+    checks: &Checks,
+    trace: Option<&Path>,
+    checkpoint: Option<&Path>,
+    resume: bool,
+    debug: bool,
+    breakpoints: &[String],
+) -> Result<bool, Box<dyn Error>> {
+    let schema = load_schema(checks.schema.as_deref())?;
+    let robot = load_robot(checks.robot.as_deref(), schema.as_ref())?;
+    let program = load_program(input, schema.as_ref())?;
+
+    let mut executor = Executor::new(SimBackend::default());
+    if let Some(robot) = robot {
+        executor.set_robot(robot);
+    }
+    if trace.is_some() {
+        executor.record_trace();
+    }
+    if let Some(checkpoint) = checkpoint {
+        executor.checkpoint_to(checkpoint);
+    }
+    if debug {
+        let mut debugger = Debugger::new(PromptHandler::new(io::stdin().lock(), io::stdout()));
+        if breakpoints.is_empty() {
+            debugger = debugger.stop_on_entry();
+        }
+        for breakpoint in breakpoints {
+            debugger = debugger.with_breakpoint(Breakpoint::parse(breakpoint));
+        }
+        executor.set_debugger(debugger);
+    }
+    if reporter.verbose {
+        executor.on_event(|event| {
+            if let Event::CommandFinished {
+                node,
+                completed,
+                total,
+                ..
+            } = event
+            {
+                println!("[{}/{}] {}", completed, total, node.text);
+            }
+        });
+    }
+
+    let result = match checkpoint {
+        Some(checkpoint) if resume && checkpoint.exists() => {
+            executor.resume_from_checkpoint(&program, &Checkpoint::load(checkpoint)?)
+        }
+        _ => executor.run(&program),
+    };
+    if let (Some(trace), Some(recorded)) = (trace, executor.trace()) {
+        recorded.save(trace)?;
+    }
+    let summary = result?;
+    match summary.status {
+        RunStatus::Completed => reporter.info(format!(
+            "Program finished after {} commands in {:.1}s",
+            summary.commands,
+            executor.backend().elapsed()
+        )),
+        RunStatus::Cancelled { path } => reporter.info(format!(
+            "Program cancelled after {} commands, before {:?}",
+            summary.commands, path
+        )),
+    }
+    Ok(true)
+}
+
+fn dump(
+    kind: DumpKind,
+    input: &Path,
+    style: Style,
+    output: Option<&Path>,
+    schema: Option<&Path>,
+) -> Result<bool, Box<dyn Error>> {
+    let unsupported = |what: &str| -> Box<dyn Error> {
+        ParseError::new(format!("The {} cannot be rendered as HTML", what)).into()
+    };
+    let rendered = match kind {
+        DumpKind::Ir => format!("{}\n", dump_ir_file(input)?),
+        DumpKind::Ast => {
+            let schema = load_schema(schema)?;
+            format!("{}\n", load_program(input, schema.as_ref())?.to_json()?)
+        }
+        DumpKind::CallGraph => {
+            let graph = call_graph_from_file(input)?;
+            match style {
+                Style::Dot => graph.to_dot(),
+                Style::Mermaid => graph.to_mermaid(),
+                Style::Html => return Err(unsupported("call graph")),
+            }
+        }
+        DumpKind::Tree => {
+            let schema = load_schema(schema)?;
+            let program = load_program(input, schema.as_ref())?;
+            match style {
+                Style::Dot => program.to_dot(),
+                Style::Mermaid => program.to_mermaid(),
+                Style::Html => program.to_html(),
+            }
+        }
+    };
+    write_output(output, &rendered)?;
+    Ok(true)
+}
+
+fn decompile(input: &Path, output: Option<&Path>) -> Result<bool, Box<dyn Error>> {
+    let program = load_program(input, None)?;
+    write_output(output, &program.decompile()?)?;
+    Ok(true)
+}
+
+/// Runs the golden file tests and test blocks. Inside a project, tests can
+/// call the functions of its source and library files.
+fn run_tests(
+    reporter: &Reporter,
+    paths: &[PathBuf],
+    checks: &Checks,
+    bless: bool,
+) -> Result<bool, Box<dyn Error>> {
+    let project = Project::find(Path::new(""))?;
+    let schema = match (&checks.schema, &project) {
+        (None, Some(project)) => project.load_schema()?,
+        (schema, _) => load_schema(schema.as_deref())?,
+    };
+    let robot = match (&checks.robot, &project) {
+        (None, Some(project)) => project.load_robot(schema.as_ref())?,
+        (robot, _) => load_robot(robot.as_deref(), schema.as_ref())?,
+    };
+
+    let tests = GoldenTest::discover(paths)?;
+    let (mut passed, mut failed, mut blessed) = (0, 0, 0);
+    for test in &tests {
+        let name = test.source.display();
+        let has_blocks = has_test_blocks(&test.source);
+        if has_blocks {
+            let cases = match &project {
+                Some(project) => project.tests(&test.source, schema.as_ref()),
+                None => tests_from_file(&test.source, schema.as_ref()),
+            };
+            match cases {
+                Ok(cases) => {
+                    for case in cases {
+                        match run_test(&case, robot.as_ref()) {
+                            Ok(()) => {
+                                passed += 1;
+                                reporter.info(format!("test {} \"{}\" ... ok", name, case.name));
+                            }
+                            Err(e) => {
+                                failed += 1;
+                                reporter
+                                    .info(format!("test {} \"{}\" ... FAILED", name, case.name));
+                                reporter.error(&test.source, e);
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    failed += 1;
+                    reporter.info(format!("test {} ... FAILED", name));
+                    reporter.error(&test.source, e);
+                }
+            }
+            // Files with test blocks are often libraries, not programs, so
+            // they only get a golden file when blessed by name.
+            let blessed_by_name = bless && paths.contains(&test.source);
+            if !test.golden.is_file() && !blessed_by_name {
+                continue;
+            }
+        }
+        let program = match &project {
+            Some(project) => project.compile(&test.source, schema.as_ref(), robot.as_ref()),
+            None => compile(&test.source, schema.as_ref(), robot.as_ref()),
+        };
+        let result = program.map(|program| (test.check(&program), program));
+        match result {
+            Ok((Ok(None), _)) => {
+                passed += 1;
+                reporter.info(format!("test {} ... ok", name));
+            }
+            Ok((_, program)) if bless => {
+                test.bless(&program)?;
+                blessed += 1;
+                reporter.info(format!(
+                    "test {} ... blessed {}",
+                    name,
+                    test.golden.display()
+                ));
             }
-        },
-        _ => {
-            eprintln!("Usage: {} <file_path> [output_path]", args[0]);
-            std::process::exit(1);
+            Ok((Ok(Some(diff)), _)) => {
+                failed += 1;
+                reporter.info(format!("test {} ... FAILED", name));
+                print!(
+                    "--- {}
++++ {}
+{}",
+                    test.golden.display(),
+                    name,
+                    diff
+                );
+            }
+            Ok((Err(e), _)) | Err(e) => {
+                failed += 1;
+                reporter.info(format!("test {} ... FAILED", name));
+                reporter.error(&test.source, e);
+            }
+        }
+    }
+    reporter.info(format!(
+        "{} passed, {} failed, {} blessed",
+        passed, failed, blessed
+    ));
+    Ok(failed == 0)
+}
+
+fn repl(checks: &Checks, sim: bool) -> Result<bool, Box<dyn Error>> {
+    let schema = load_schema(checks.schema.as_deref())?;
+    let robot = load_robot(checks.robot.as_deref(), schema.as_ref())?;
+    let mut session = Session::new(schema, robot);
+    session.set_simulating(sim);
+    let mut editor = DefaultEditor::new()?;
+    let history = env::var_os("HOME").map(|home| Path::new(&home).join(".klang_history"));
+    if let Some(history) = &history {
+        // There is no history yet the first time.
+        let _ = editor.load_history(history);
+    }
+    println!("klang {}, :help for commands", env!("CARGO_PKG_VERSION"));
+
+    let mut buffer = String::new();
+    loop {
+        let prompt = if buffer.is_empty() {
+            "klang> "
+        } else {
+            "  ...> "
+        };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                buffer.push_str(&line);
+                buffer.push('\n');
+                if is_incomplete(&buffer) {
+                    continue;
+                }
+                let input = std::mem::take(&mut buffer);
+                if input.trim().is_empty() {
+                    continue;
+                }
+                editor.add_history_entry(input.trim_end())?;
+                if matches!(input.trim(), ":quit" | ":q") {
+                    break;
+                }
+                match session.eval(&input) {
+                    Ok(output) => print!("{}", output),
+                    Err(e) => eprintln!("error: {}", e),
+                }
+            }
+            Err(ReadlineError::Interrupted) => buffer.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if let Some(history) = &history {
+        editor.save_history(history)?;
+    }
+    Ok(true)
+}
+
+fn docs(
+    inputs: &[PathBuf],
+    format: DocsFormat,
+    title: &str,
+    output: Option<&Path>,
+) -> Result<bool, Box<dyn Error>> {
+    let mut reference = Reference::new(title);
+    for input in inputs {
+        reference
+            .add_file(input)
+            .map_err(|e| format!("{}: {}", input.display(), e))?;
+    }
+    let rendered = match format {
+        DocsFormat::Markdown => reference.to_markdown(),
+        DocsFormat::Html => reference.to_html(),
+    };
+    write_output(output, &rendered)?;
+    Ok(true)
+}
+
+fn estimate(
+    input: &Path,
+    schema: Option<&Path>,
+    durations: Option<&Path>,
+) -> Result<bool, Box<dyn Error>> {
+    let schema = load_schema(schema)?;
+    let program = load_program(input, schema.as_ref())?;
+    let mut model = schema
+        .as_ref()
+        .map(DurationModel::from_schema)
+        .unwrap_or_default();
+    if let Some(durations) = durations {
+        model.extend(DurationModel::load(durations)?);
+    }
+    print!("{}", program.estimate_duration(&model));
+    Ok(true)
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if let Command::Build {
+        format,
+        deduplicate: true,
+        ..
+    } = &cli.command
+    {
+        if *format != OutputFormat::Binary {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--deduplicate only applies to the binary format",
+                )
+                .exit();
+        }
+    }
+    let reporter = Reporter {
+        quiet: cli.quiet,
+        verbose: cli.verbose,
+    };
+    let result = match &cli.command {
+        Command::Build {
+            inputs,
+            manifest,
+            output,
+            format,
+            deduplicate,
+            checks,
+            watch: options,
+        } if inputs.is_empty() => build_project(
+            &reporter,
+            manifest.as_deref(),
+            output.as_deref(),
+            *format,
+            *deduplicate,
+            checks,
+            options,
+        ),
+        Command::Build {
+            inputs,
+            output,
+            format,
+            deduplicate,
+            checks,
+            watch: options,
+            ..
+        } if options.watch => watch(
+            &reporter,
+            || Ok(watched_files(inputs, checks)),
+            || {
+                let built = build(
+                    &reporter,
+                    inputs,
+                    output.as_deref(),
+                    *format,
+                    *deduplicate,
+                    checks,
+                )?;
+                if built && options.sim {
+                    simulate_files(&reporter, inputs, checks)?;
+                }
+                Ok(built)
+            },
+        ),
+        Command::Build {
+            inputs,
+            output,
+            format,
+            deduplicate,
+            checks,
+            ..
+        } => build(
+            &reporter,
+            inputs,
+            output.as_deref(),
+            *format,
+            *deduplicate,
+            checks,
+        ),
+        Command::Check {
+            inputs,
+            checks,
+            watch: options,
+        } if options.watch => watch(
+            &reporter,
+            || Ok(watched_files(inputs, checks)),
+            || {
+                let checked = check(&reporter, inputs, checks)?;
+                if checked && options.sim {
+                    simulate_files(&reporter, inputs, checks)?;
+                }
+                Ok(checked)
+            },
+        ),
+        Command::Check { inputs, checks, .. } => check(&reporter, inputs, checks),
+        Command::Fmt { inputs, check } => format_files(&reporter, inputs, *check),
+        Command::Rename {
+            input,
+            from,
+            to,
+            function,
+        } => rename(&reporter, input, from, to, function.as_deref()),
+        Command::Run {
+            input,
+            checks,
+            trace,
+            checkpoint,
+            resume,
+            debug,
+            breakpoints,
+        } => run(
+            &reporter,
+            input,
+            checks,
+            trace.as_deref(),
+            checkpoint.as_deref(),
+            *resume,
+            *debug,
+            breakpoints,
+        ),
+        Command::Test {
+            paths,
+            checks,
+            bless,
+        } => run_tests(&reporter, paths, checks, *bless),
+        Command::Repl { checks, sim } => repl(checks, *sim),
+        Command::Dump {
+            kind,
+            input,
+            style,
+            output,
+            schema,
+        } => dump(*kind, input, *style, output.as_deref(), schema.as_deref()),
+        Command::Decompile { input, output } => decompile(input, output.as_deref()),
+        Command::Docs {
+            inputs,
+            format,
+            title,
+            output,
+        } => docs(inputs, *format, title, output.as_deref()),
+        Command::Estimate {
+            input,
+            schema,
+            durations,
+        } => estimate(input, schema.as_deref(), durations.as_deref()),
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(e) if e.is::<ExecutionError>() => {
+            eprintln!("error: {}", e);
+            ExitCode::from(3)
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::from(1)
         }
     }
 }
@@ -0,0 +1,95 @@
+use super::errors::ExecutionError;
+use super::Frame;
+use crate::parser::structs::{KlangProgram, Node};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// The FNV-1a hash of the bytes, which is the same on every platform and
+/// with every build of the compiler.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Identifies a compiled program, as the FNV-1a hash of its encoded AST.
+pub fn program_hash(program: &KlangProgram) -> u64 {
+    fnv1a(&program.to_ast().encode_to_vec())
+}
+
+/// The position of a run after its last completed command, so it can be
+/// resumed after a restart.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub program_hash: u64,
+    /// The path of the last completed command.
+    pub path: Vec<usize>,
+    pub completed: usize,
+    /// The function calls enclosing the command, with their bound arguments.
+    pub call_stack: Vec<Frame>,
+}
+
+impl Checkpoint {
+    pub fn to_json(&self) -> Result<String, ExecutionError> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| ExecutionError::new(format!("Error writing checkpoint: {}", e)))
+    }
+
+    pub fn from_json(input: &str) -> Result<Self, ExecutionError> {
+        serde_json::from_str(input)
+            .map_err(|e| ExecutionError::new(format!("Error reading checkpoint: {}", e)))
+    }
+
+    /// Writes the checkpoint to a temporary file first and renames it into
+    /// place, so a crash never leaves a partial checkpoint behind.
+    pub fn save(&self, path: &Path) -> Result<(), ExecutionError> {
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, self.to_json()?)?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ExecutionError> {
+        Checkpoint::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// Fails unless the checkpoint was taken while running `program`.
+    pub fn check_program(&self, program: &KlangProgram) -> Result<(), ExecutionError> {
+        let expected = program_hash(program);
+        if self.program_hash != expected {
+            return Err(ExecutionError::new(format!(
+                "Checkpoint is for a different program (hash {:016x}, expected {:016x})",
+                self.program_hash, expected
+            )));
+        }
+        Ok(())
+    }
+
+    /// The path of the command to run next, or None if the program had
+    /// already finished.
+    pub fn next_path(&self, program: &KlangProgram) -> Result<Option<Vec<usize>>, ExecutionError> {
+        let mut paths = Vec::new();
+        command_paths(&program.program, &mut Vec::new(), &mut paths);
+        let position = paths
+            .iter()
+            .position(|path| *path == self.path)
+            .ok_or_else(|| {
+                ExecutionError::new(format!("Checkpoint path {:?} is not a command", self.path))
+            })?;
+        Ok(paths.into_iter().nth(position + 1))
+    }
+}
+
+fn command_paths(nodes: &[Node], path: &mut Vec<usize>, paths: &mut Vec<Vec<usize>>) {
+    for (i, node) in nodes.iter().enumerate() {
+        path.push(i);
+        if node.is_function() {
+            command_paths(&node.children, path, paths);
+        } else {
+            paths.push(path.clone());
+        }
+        path.pop();
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::{Arc, Condvar, Mutex};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Running,
+    Paused,
+    Cancelled,
+}
+
+/// Lets another thread pause, resume or cancel a running program. Requests
+/// take effect before the next command is sent to the backend.
+#[derive(Clone)]
+pub struct ControlHandle {
+    state: Arc<(Mutex<State>, Condvar)>,
+}
+
+impl Default for ControlHandle {
+    fn default() -> Self {
+        ControlHandle {
+            state: Arc::new((Mutex::new(State::Running), Condvar::new())),
+        }
+    }
+}
+
+impl ControlHandle {
+    fn set(&self, state: State) {
+        let (lock, condvar) = &*self.state;
+        let mut current = lock.lock().unwrap();
+        // A cancelled program stays cancelled.
+        if *current != State::Cancelled {
+            *current = state;
+        }
+        condvar.notify_all();
+    }
+
+    fn get(&self) -> State {
+        *self.state.0.lock().unwrap()
+    }
+
+    pub fn pause(&self) {
+        self.set(State::Paused);
+    }
+
+    pub fn resume(&self) {
+        self.set(State::Running);
+    }
+
+    pub fn cancel(&self) {
+        self.set(State::Cancelled);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.get() == State::Paused
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.get() == State::Cancelled
+    }
+
+    /// Clears a previous cancellation so the handle can be used for
+    /// another run.
+    pub(crate) fn reset(&self) {
+        let mut state = self.state.0.lock().unwrap();
+        if *state == State::Cancelled {
+            *state = State::Running;
+        }
+    }
+
+    /// Blocks while the program is paused. Returns false if it was
+    /// cancelled.
+    pub(crate) fn wait_while_paused(&self) -> bool {
+        let (lock, condvar) = &*self.state;
+        let state = condvar
+            .wait_while(lock.lock().unwrap(), |state| *state == State::Paused)
+            .unwrap();
+        *state != State::Cancelled
+    }
+}
@@ -0,0 +1,250 @@
+use super::Frame;
+use crate::parser::structs::Node;
+use std::io::{BufRead, Write};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Breakpoint {
+    /// Pauses on any command or function call on this source line.
+    Line(usize),
+    /// Pauses on calls to the function with this signature, such as
+    /// `wave [arm] arm`.
+    Function(String),
+}
+
+impl Breakpoint {
+    /// Parses a line number, or otherwise treats the text as a signature.
+    pub fn parse(text: &str) -> Breakpoint {
+        match text.trim().parse() {
+            Ok(line) => Breakpoint::Line(line),
+            Err(_) => Breakpoint::Function(text.trim().to_string()),
+        }
+    }
+
+    fn matches(&self, node: &Node) -> bool {
+        match self {
+            Breakpoint::Line(line) => node.location.map(|location| location.line) == Some(*line),
+            Breakpoint::Function(signature) => node.function.as_ref() == Some(signature),
+        }
+    }
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Breakpoint::Line(line) => write!(f, "line {}", line),
+            Breakpoint::Function(signature) => write!(f, "function '{}'", signature),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugCommand {
+    /// Runs until the next breakpoint.
+    Continue,
+    /// Pauses at the next node, entering function calls.
+    StepInto,
+    /// Pauses at the next node at the same depth or shallower, running
+    /// function calls to completion.
+    StepOver,
+    /// Pauses at the next node after the current function returns.
+    StepOut,
+    /// Stops the program.
+    Abort,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PauseReason {
+    Entry,
+    Breakpoint(Breakpoint),
+    Step,
+}
+
+/// The state of the program at a pause. The node is about to be executed,
+/// or for function calls, entered.
+pub struct PauseState<'a> {
+    pub node: &'a Node,
+    pub path: &'a [usize],
+    pub call_stack: &'a [Frame],
+    pub reason: PauseReason,
+}
+
+/// Decides what to do each time the program pauses. The handler may also
+/// change the breakpoints.
+pub trait DebugHandler {
+    fn paused(&mut self, state: &PauseState, breakpoints: &mut Vec<Breakpoint>) -> DebugCommand;
+}
+
+impl<F: FnMut(&PauseState, &mut Vec<Breakpoint>) -> DebugCommand> DebugHandler for F {
+    fn paused(&mut self, state: &PauseState, breakpoints: &mut Vec<Breakpoint>) -> DebugCommand {
+        self(state, breakpoints)
+    }
+}
+
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    handler: Box<dyn DebugHandler>,
+    command: DebugCommand,
+    /// Depth of the node where the last step started.
+    step_depth: usize,
+    entry: bool,
+}
+
+impl Debugger {
+    pub fn new(handler: impl DebugHandler + 'static) -> Self {
+        Debugger {
+            breakpoints: Vec::new(),
+            handler: Box::new(handler),
+            command: DebugCommand::Continue,
+            step_depth: 0,
+            entry: false,
+        }
+    }
+
+    /// Pauses before the first node of the program.
+    pub fn stop_on_entry(mut self) -> Self {
+        self.entry = true;
+        self
+    }
+
+    pub fn with_breakpoint(mut self, breakpoint: Breakpoint) -> Self {
+        self.breakpoints.push(breakpoint);
+        self
+    }
+
+    /// Called by the executor before each node. Returns false if the
+    /// program should stop.
+    pub(crate) fn visit(&mut self, node: &Node, path: &[usize], call_stack: &[Frame]) -> bool {
+        let depth = call_stack.len();
+        let reason = if std::mem::take(&mut self.entry) {
+            Some(PauseReason::Entry)
+        } else if let Some(breakpoint) = self.breakpoints.iter().find(|bp| bp.matches(node)) {
+            Some(PauseReason::Breakpoint(breakpoint.clone()))
+        } else {
+            match self.command {
+                DebugCommand::StepInto => Some(PauseReason::Step),
+                DebugCommand::StepOver if depth <= self.step_depth => Some(PauseReason::Step),
+                DebugCommand::StepOut if depth < self.step_depth => Some(PauseReason::Step),
+                _ => None,
+            }
+        };
+        let Some(reason) = reason else {
+            return true;
+        };
+
+        let state = PauseState {
+            node,
+            path,
+            call_stack,
+            reason,
+        };
+        self.command = self.handler.paused(&state, &mut self.breakpoints);
+        self.step_depth = depth;
+        self.command != DebugCommand::Abort
+    }
+}
+
+const HELP: &str = "Commands:
+  s, step          step into the next node
+  n, next          step over function calls
+  o, out           run until the current function returns
+  c, continue      run until the next breakpoint
+  b, break <arg>   add a breakpoint on a line number or function signature
+  d, delete <arg>  remove a breakpoint
+  bt, stack        show the call stack and bound arguments
+  q, quit          stop the program";
+
+/// A debug handler that reads commands from a prompt, such as stdin.
+pub struct PromptHandler<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> PromptHandler<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        PromptHandler { input, output }
+    }
+
+    fn describe(&mut self, state: &PauseState) -> std::io::Result<()> {
+        let reason = match &state.reason {
+            PauseReason::Entry => "entry".to_string(),
+            PauseReason::Breakpoint(breakpoint) => format!("breakpoint on {}", breakpoint),
+            PauseReason::Step => "step".to_string(),
+        };
+        let location = match state.node.location {
+            Some(location) => format!(" at {}", location),
+            None => String::new(),
+        };
+        let kind = if state.node.is_function() {
+            "call"
+        } else {
+            "command"
+        };
+        writeln!(
+            self.output,
+            "Paused ({}){}: {} {}",
+            reason, location, kind, state.node.text
+        )
+    }
+
+    fn print_stack(&mut self, state: &PauseState) -> std::io::Result<()> {
+        if state.call_stack.is_empty() {
+            writeln!(self.output, "  <program>")?;
+        }
+        for (depth, frame) in state.call_stack.iter().enumerate() {
+            let arguments = frame
+                .arguments
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<String>>()
+                .join(", ");
+            writeln!(
+                self.output,
+                "  #{} {} [{}] ({})",
+                depth, frame.text, frame.function, arguments
+            )?;
+        }
+        Ok(())
+    }
+
+    fn prompt(
+        &mut self,
+        state: &PauseState,
+        breakpoints: &mut Vec<Breakpoint>,
+    ) -> std::io::Result<DebugCommand> {
+        self.describe(state)?;
+        loop {
+            write!(self.output, "(kdb) ")?;
+            self.output.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(DebugCommand::Abort);
+            }
+            let (command, argument) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            match command {
+                "s" | "step" => return Ok(DebugCommand::StepInto),
+                "n" | "next" => return Ok(DebugCommand::StepOver),
+                "o" | "out" => return Ok(DebugCommand::StepOut),
+                "c" | "continue" => return Ok(DebugCommand::Continue),
+                "q" | "quit" => return Ok(DebugCommand::Abort),
+                "bt" | "stack" => self.print_stack(state)?,
+                "b" | "break" if !argument.is_empty() => {
+                    let breakpoint = Breakpoint::parse(argument);
+                    writeln!(self.output, "Breakpoint on {}", breakpoint)?;
+                    breakpoints.push(breakpoint);
+                }
+                "d" | "delete" if !argument.is_empty() => {
+                    let breakpoint = Breakpoint::parse(argument);
+                    breakpoints.retain(|existing| *existing != breakpoint);
+                }
+                _ => writeln!(self.output, "{}", HELP)?,
+            }
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> DebugHandler for PromptHandler<R, W> {
+    fn paused(&mut self, state: &PauseState, breakpoints: &mut Vec<Breakpoint>) -> DebugCommand {
+        self.prompt(state, breakpoints)
+            .unwrap_or(DebugCommand::Abort)
+    }
+}
@@ -0,0 +1,40 @@
+use crate::parser::structs::Node;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub struct ExecutionError {
+    pub message: String,
+}
+
+impl ExecutionError {
+    pub fn new(message: String) -> ExecutionError {
+        ExecutionError { message }
+    }
+
+    pub fn from_node(message: String, node: &Node) -> ExecutionError {
+        match node.location {
+            Some(location) => ExecutionError {
+                message: format!(
+                    "{} (line: {}, column: {})",
+                    message, location.line, location.column
+                ),
+            },
+            None => ExecutionError::new(message),
+        }
+    }
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ExecutionError {}
+
+impl From<std::io::Error> for ExecutionError {
+    fn from(error: std::io::Error) -> Self {
+        ExecutionError::new(format!("{}", error))
+    }
+}
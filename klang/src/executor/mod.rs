@@ -0,0 +1,453 @@
+pub mod checkpoint;
+pub mod control;
+pub mod debugger;
+pub mod errors;
+pub mod sim;
+pub mod trace;
+
+use crate::parser::robot::RobotDescription;
+use crate::parser::structs::{KlangProgram, Node};
+use checkpoint::{program_hash, Checkpoint};
+use control::ControlHandle;
+use debugger::Debugger;
+use errors::ExecutionError;
+use serde::{Deserialize, Serialize};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::time::Instant;
+use trace::{Trace, TraceEntry};
+
+/// The result of a single command on the robot.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Outcome {
+    Success,
+    Failure(String),
+}
+
+/// Something that can carry out leaf commands, such as a real robot or a
+/// simulator. Returning an error means the backend itself is broken, while
+/// `Outcome::Failure` means the command was attempted and did not succeed.
+pub trait RobotBackend {
+    fn execute(&mut self, command: &Node) -> Result<Outcome, ExecutionError>;
+
+    /// Called when the program is cancelled, to bring the robot to a safe
+    /// stop.
+    fn stop(&mut self) -> Result<(), ExecutionError> {
+        Ok(())
+    }
+}
+
+impl<B: RobotBackend + ?Sized> RobotBackend for &mut B {
+    fn execute(&mut self, command: &Node) -> Result<Outcome, ExecutionError> {
+        (**self).execute(command)
+    }
+
+    fn stop(&mut self) -> Result<(), ExecutionError> {
+        (**self).stop()
+    }
+}
+
+/// Progress reported while a program runs. `path` is the position of the
+/// node in the program tree, as child indices starting from the top level.
+pub enum Event<'a> {
+    EnterFunction {
+        path: &'a [usize],
+        node: &'a Node,
+    },
+    ExitFunction {
+        path: &'a [usize],
+        node: &'a Node,
+    },
+    CommandStarted {
+        path: &'a [usize],
+        node: &'a Node,
+    },
+    CommandFinished {
+        path: &'a [usize],
+        node: &'a Node,
+        outcome: &'a Outcome,
+        completed: usize,
+        total: usize,
+    },
+    Paused {
+        path: &'a [usize],
+    },
+    Resumed {
+        path: &'a [usize],
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RunStatus {
+    Completed,
+    /// The program was cancelled before running the command at `path`.
+    /// Passing the path to `Executor::resume` continues from there.
+    Cancelled {
+        path: Vec<usize>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Summary {
+    /// Commands executed by this run, not counting any skipped by resuming.
+    pub commands: usize,
+    pub status: RunStatus,
+}
+
+/// A function call that is currently being executed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    pub text: String,
+    pub function: String,
+    pub arguments: Vec<(String, String)>,
+    pub path: Vec<usize>,
+}
+
+type Listener = Box<dyn FnMut(&Event)>;
+
+/// Walks a compiled program, sending each leaf command to the backend in
+/// order. Function nodes are entered as scopes and their children executed
+/// in turn.
+pub struct Executor<B: RobotBackend> {
+    backend: B,
+    listeners: Vec<Listener>,
+    stack: Vec<Frame>,
+    completed: usize,
+    skipped: usize,
+    total: usize,
+    trace: Option<Trace>,
+    started: Option<Instant>,
+    debugger: Option<Debugger>,
+    control: ControlHandle,
+    cancelled_at: Option<Vec<usize>>,
+    checkpoint: Option<PathBuf>,
+    program_hash: u64,
+    robot: Option<RobotDescription>,
+}
+
+/// Fails unless `path` leads to a node of the program.
+fn check_path(nodes: &[Node], path: &[usize]) -> Result<(), ExecutionError> {
+    let mut nodes = nodes;
+    for (depth, &index) in path.iter().enumerate() {
+        let Some(node) = nodes.get(index) else {
+            return Err(ExecutionError::new(format!(
+                "Invalid resume path {:?}: the program has no node at {:?}",
+                path,
+                &path[..=depth]
+            )));
+        };
+        nodes = &node.children;
+    }
+    Ok(())
+}
+
+fn count_commands(nodes: &[Node]) -> usize {
+    nodes
+        .iter()
+        .map(|node| {
+            if node.is_function() {
+                count_commands(&node.children)
+            } else {
+                1
+            }
+        })
+        .sum()
+}
+
+impl<B: RobotBackend> Executor<B> {
+    pub fn new(backend: B) -> Self {
+        Executor {
+            backend,
+            listeners: Vec::new(),
+            stack: Vec::new(),
+            completed: 0,
+            skipped: 0,
+            total: 0,
+            trace: None,
+            started: None,
+            debugger: None,
+            control: ControlHandle::default(),
+            cancelled_at: None,
+            checkpoint: None,
+            program_hash: 0,
+            robot: None,
+        }
+    }
+
+    /// Refuses to send any command outside the robot's joint limits to the
+    /// backend.
+    pub fn set_robot(&mut self, robot: RobotDescription) -> &mut Self {
+        self.robot = Some(robot);
+        self
+    }
+
+    /// Saves a checkpoint to `path` after every completed command, from the
+    /// next run onwards.
+    pub fn checkpoint_to(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.checkpoint = Some(path.into());
+        self
+    }
+
+    /// A handle for pausing, resuming or cancelling runs of this executor
+    /// from another thread.
+    pub fn control_handle(&self) -> ControlHandle {
+        self.control.clone()
+    }
+
+    /// Pauses the program at breakpoints and steps, as decided by the
+    /// debugger's handler.
+    pub fn set_debugger(&mut self, debugger: Debugger) -> &mut Self {
+        self.debugger = Some(debugger);
+        self
+    }
+
+    /// Records every executed command from the next run onwards.
+    pub fn record_trace(&mut self) -> &mut Self {
+        self.trace = Some(Trace::default());
+        self
+    }
+
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+
+    pub fn on_event(&mut self, listener: impl FnMut(&Event) + 'static) -> &mut Self {
+        self.listeners.push(Box::new(listener));
+        self
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn into_backend(self) -> B {
+        self.backend
+    }
+
+    /// The function calls enclosing the node being executed, outermost first.
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.stack
+    }
+
+    pub fn run(&mut self, program: &KlangProgram) -> Result<Summary, ExecutionError> {
+        self.resume(program, &[])
+    }
+
+    /// Runs the program starting from the node at `path`, skipping all the
+    /// commands before it.
+    pub fn resume(
+        &mut self,
+        program: &KlangProgram,
+        path: &[usize],
+    ) -> Result<Summary, ExecutionError> {
+        check_path(&program.program, path)?;
+        self.stack.clear();
+        self.completed = 0;
+        self.skipped = 0;
+        self.total = count_commands(&program.program);
+        self.started = Some(Instant::now());
+        self.cancelled_at = None;
+        if self.checkpoint.is_some() {
+            self.program_hash = program_hash(program);
+        }
+        if let Some(trace) = &mut self.trace {
+            trace.entries.clear();
+        }
+
+        let mut current = Vec::new();
+        let result = self.run_nodes(&program.program, &mut current, path);
+        // A cancel issued before the run started still stops it, so it is
+        // only cleared once the run is over.
+        self.control.reset();
+        let status = match result? {
+            ControlFlow::Break(()) => RunStatus::Cancelled {
+                path: self.cancelled_at.take().unwrap_or_default(),
+            },
+            ControlFlow::Continue(()) => RunStatus::Completed,
+        };
+        Ok(Summary {
+            commands: self.completed - self.skipped,
+            status,
+        })
+    }
+
+    /// Continues a run of the same program after the command recorded in
+    /// the checkpoint. Refuses to run a program that has changed since.
+    pub fn resume_from_checkpoint(
+        &mut self,
+        program: &KlangProgram,
+        checkpoint: &Checkpoint,
+    ) -> Result<Summary, ExecutionError> {
+        checkpoint.check_program(program)?;
+        match checkpoint.next_path(program)? {
+            Some(path) => self.resume(program, &path),
+            None => Ok(Summary {
+                commands: 0,
+                status: RunStatus::Completed,
+            }),
+        }
+    }
+
+    fn save_checkpoint(&self, path: &[usize]) -> Result<(), ExecutionError> {
+        if let Some(file) = &self.checkpoint {
+            Checkpoint {
+                program_hash: self.program_hash,
+                path: path.to_vec(),
+                completed: self.completed,
+                call_stack: self.stack.clone(),
+            }
+            .save(file)?;
+        }
+        Ok(())
+    }
+
+    fn elapsed(&self) -> f64 {
+        self.started
+            .map(|started| started.elapsed().as_secs_f64())
+            .unwrap_or_default()
+    }
+
+    fn emit(&mut self, event: Event) {
+        for listener in &mut self.listeners {
+            listener(&event);
+        }
+    }
+
+    fn run_nodes(
+        &mut self,
+        nodes: &[Node],
+        path: &mut Vec<usize>,
+        start: &[usize],
+    ) -> Result<ControlFlow<()>, ExecutionError> {
+        for (i, node) in nodes.iter().enumerate() {
+            let node_start = match start.split_first() {
+                Some((&first, _)) if i < first => {
+                    self.skipped += count_commands(std::slice::from_ref(node));
+                    self.completed = self.skipped;
+                    continue;
+                }
+                Some((&first, rest)) if i == first => rest,
+                _ => &[],
+            };
+
+            path.push(i);
+            if let Some(debugger) = &mut self.debugger {
+                if !debugger.visit(node, path, &self.stack) {
+                    return Err(ExecutionError::from_node(
+                        "Execution aborted by debugger".to_string(),
+                        node,
+                    ));
+                }
+            }
+            let result = if node.is_function() {
+                self.run_function(node, path, node_start)
+            } else {
+                self.run_command(node, path)
+            };
+            path.pop();
+            if result?.is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    fn run_function(
+        &mut self,
+        node: &Node,
+        path: &mut Vec<usize>,
+        start: &[usize],
+    ) -> Result<ControlFlow<()>, ExecutionError> {
+        self.emit(Event::EnterFunction { path, node });
+        self.stack.push(Frame {
+            text: node.text.clone(),
+            function: node.function.clone().unwrap_or_default(),
+            arguments: node.arguments.clone(),
+            path: path.clone(),
+        });
+        if self.run_nodes(&node.children, path, start)?.is_break() {
+            return Ok(ControlFlow::Break(()));
+        }
+        self.stack.pop();
+        self.emit(Event::ExitFunction { path, node });
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Waits out any pause, and stops the backend if the program has been
+    /// cancelled.
+    fn check_control(&mut self, path: &[usize]) -> Result<ControlFlow<()>, ExecutionError> {
+        if self.control.is_paused() {
+            self.emit(Event::Paused { path });
+            let running = self.control.wait_while_paused();
+            if running {
+                self.emit(Event::Resumed { path });
+            }
+        }
+        if self.control.is_cancelled() {
+            self.backend.stop()?;
+            self.cancelled_at = Some(path.to_vec());
+            return Ok(ControlFlow::Break(()));
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    fn run_command(
+        &mut self,
+        node: &Node,
+        path: &[usize],
+    ) -> Result<ControlFlow<()>, ExecutionError> {
+        if self.check_control(path)?.is_break() {
+            return Ok(ControlFlow::Break(()));
+        }
+        if let Some(robot) = &self.robot {
+            let violations = robot.check_command(node);
+            if !violations.is_empty() {
+                return Err(ExecutionError::from_node(
+                    format!("Safety check failed: {}", violations.join("; ")),
+                    node,
+                ));
+            }
+        }
+        self.emit(Event::CommandStarted { path, node });
+        let start = self.elapsed();
+        let outcome = self.backend.execute(node)?;
+        let end = self.elapsed();
+        if let Some(trace) = &mut self.trace {
+            trace.entries.push(TraceEntry {
+                path: path.to_vec(),
+                command: node.text.clone(),
+                action: node.action.clone(),
+                location: node.location,
+                start,
+                end,
+                outcome: outcome.clone(),
+            });
+        }
+        self.completed += 1;
+        self.emit(Event::CommandFinished {
+            path,
+            node,
+            outcome: &outcome,
+            completed: self.completed,
+            total: self.total,
+        });
+        match outcome {
+            Outcome::Success => {
+                self.save_checkpoint(path)?;
+                Ok(ControlFlow::Continue(()))
+            }
+            Outcome::Failure(reason) => Err(ExecutionError::from_node(
+                format!("Command failed: {}: {}", node.text, reason),
+                node,
+            )),
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use super::errors::ExecutionError;
+use super::{Outcome, RobotBackend};
+use crate::parser::robot::TARGET_ARGUMENTS;
+use crate::parser::schema::Value;
+use crate::parser::structs::{Action, Node};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Clone, Debug)]
+pub struct SimConfig {
+    /// Seconds taken by a command with no more specific duration.
+    pub default_duration: f64,
+    /// Seconds taken per command, keyed by action name or command text.
+    pub durations: HashMap<String, f64>,
+    /// If set, joint moves take as long as the joint needs to reach its
+    /// target at this speed, in units per second. Speeds that are not
+    /// positive are ignored.
+    pub joint_velocity: Option<f64>,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            default_duration: 1.0,
+            durations: HashMap::new(),
+            joint_velocity: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct InjectedFailure {
+    pattern: Option<String>,
+    index: Option<usize>,
+    message: String,
+}
+
+/// A robot backend that tracks a simple kinematic state instead of moving
+/// hardware. Commands must have been lowered into actions with a primitive
+/// schema to affect the state; other commands only take time.
+///
+/// - An action with a numeric `angle`, `position` or `target` argument moves
+///   a joint, named by its other arguments, such as `joint=1 arm=right`.
+/// - `open_gripper` and `close_gripper` actions set a gripper, named the
+///   same way, or `gripper` if the action has no arguments.
+/// - A `wait` action with a `seconds` argument takes that long.
+#[derive(Clone, Debug, Default)]
+pub struct SimBackend {
+    pub config: SimConfig,
+    joints: BTreeMap<String, f64>,
+    grippers: BTreeMap<String, bool>,
+    elapsed: f64,
+    history: Vec<String>,
+    failures: Vec<InjectedFailure>,
+}
+
+fn argument_key(action: &Action, skip: &[&str]) -> String {
+    action
+        .arguments
+        .iter()
+        .filter(|(name, _)| !skip.contains(&name.as_str()))
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn joint_target(action: &Action) -> Option<(String, f64)> {
+    TARGET_ARGUMENTS.iter().find_map(|name| {
+        let target = action.argument(name)?.as_number()?;
+        Some((argument_key(action, &[name]), target))
+    })
+}
+
+impl SimBackend {
+    pub fn new(config: SimConfig) -> Self {
+        SimBackend {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Makes every command whose text contains `pattern`, or whose action
+    /// has that name, fail with `message`.
+    pub fn inject_failure(&mut self, pattern: &str, message: &str) {
+        self.failures.push(InjectedFailure {
+            pattern: Some(pattern.to_string()),
+            index: None,
+            message: message.to_string(),
+        });
+    }
+
+    /// Makes the command at `index` in execution order fail with `message`.
+    pub fn inject_failure_at(&mut self, index: usize, message: &str) {
+        self.failures.push(InjectedFailure {
+            pattern: None,
+            index: Some(index),
+            message: message.to_string(),
+        });
+    }
+
+    pub fn joint_position(&self, joint: &str) -> Option<f64> {
+        self.joints.get(joint).copied()
+    }
+
+    pub fn joints(&self) -> &BTreeMap<String, f64> {
+        &self.joints
+    }
+
+    /// Gripper states, where `true` means open.
+    pub fn grippers(&self) -> &BTreeMap<String, bool> {
+        &self.grippers
+    }
+
+    /// Simulated seconds spent executing commands so far.
+    pub fn elapsed(&self) -> f64 {
+        self.elapsed
+    }
+
+    /// Text of every command executed so far, in order.
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    fn injected_failure(&self, command: &Node) -> Option<String> {
+        let index = self.history.len();
+        let action_name = command.action.as_ref().map(|action| action.name.as_str());
+        self.failures
+            .iter()
+            .find(|failure| match (&failure.pattern, failure.index) {
+                (Some(pattern), _) => {
+                    command.text.contains(pattern.as_str()) || action_name == Some(pattern)
+                }
+                (None, Some(failure_index)) => failure_index == index,
+                (None, None) => false,
+            })
+            .map(|failure| failure.message.clone())
+    }
+
+    fn duration(&self, command: &Node) -> f64 {
+        let action = command.action.as_ref();
+        if let Some(duration) = action
+            .and_then(|action| self.config.durations.get(&action.name))
+            .or_else(|| self.config.durations.get(&command.text))
+        {
+            return *duration;
+        }
+        let Some(action) = action else {
+            return self.config.default_duration;
+        };
+        if action.name == "wait" {
+            if let Some(seconds) = action.argument("seconds").and_then(Value::as_number) {
+                return seconds;
+            }
+        }
+        let velocity = self
+            .config
+            .joint_velocity
+            .filter(|velocity| velocity.is_finite() && *velocity > 0.0);
+        if let (Some(velocity), Some((joint, target))) = (velocity, joint_target(action)) {
+            let current = self.joints.get(&joint).copied().unwrap_or(0.0);
+            return (target - current).abs() / velocity;
+        }
+        self.config.default_duration
+    }
+
+    fn apply(&mut self, action: &Action) {
+        if let Some((joint, target)) = joint_target(action) {
+            self.joints.insert(joint, target);
+            return;
+        }
+        let open = match action.name.as_str() {
+            "open_gripper" => true,
+            "close_gripper" => false,
+            _ => return,
+        };
+        let gripper = match argument_key(action, &[]) {
+            key if key.is_empty() => "gripper".to_string(),
+            key => key,
+        };
+        self.grippers.insert(gripper, open);
+    }
+}
+
+impl RobotBackend for SimBackend {
+    fn execute(&mut self, command: &Node) -> Result<Outcome, ExecutionError> {
+        self.elapsed += self.duration(command);
+        let failure = self.injected_failure(command);
+        self.history.push(command.text.clone());
+        if let Some(message) = failure {
+            return Ok(Outcome::Failure(message));
+        }
+        if let Some(action) = &command.action {
+            self.apply(action);
+        }
+        Ok(Outcome::Success)
+    }
+}
@@ -0,0 +1,106 @@
+use super::errors::ExecutionError;
+use super::{Outcome, RobotBackend};
+use crate::parser::structs::{Action, Node, SourceLocation};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// One executed command. Times are in seconds since the start of the run.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TraceEntry {
+    pub path: Vec<usize>,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<Action>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<SourceLocation>,
+    pub start: f64,
+    pub end: f64,
+    pub outcome: Outcome,
+}
+
+/// The commands executed during a run, stored as JSON lines with one entry
+/// per line.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Trace {
+    pub entries: Vec<TraceEntry>,
+}
+
+impl Trace {
+    pub fn to_jsonl(&self) -> Result<String, ExecutionError> {
+        let mut result = String::new();
+        for entry in &self.entries {
+            let line = serde_json::to_string(entry)
+                .map_err(|e| ExecutionError::new(format!("Error writing trace: {}", e)))?;
+            result.push_str(&line);
+            result.push('\n');
+        }
+        Ok(result)
+    }
+
+    pub fn from_jsonl(input: &str) -> Result<Self, ExecutionError> {
+        let entries = input
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|e| {
+                    ExecutionError::new(format!("Error reading trace line {}: {}", i + 1, e))
+                })
+            })
+            .collect::<Result<Vec<TraceEntry>, ExecutionError>>()?;
+        Ok(Trace { entries })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ExecutionError> {
+        fs::write(path, self.to_jsonl()?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ExecutionError> {
+        Trace::from_jsonl(&fs::read_to_string(path)?)
+    }
+}
+
+/// A backend that answers each command with the outcome recorded in a
+/// trace, so a run can be reproduced without the robot. Fails if the
+/// program asks for a different command than the one recorded.
+pub struct ReplayBackend {
+    trace: Trace,
+    position: usize,
+}
+
+impl ReplayBackend {
+    pub fn new(trace: Trace) -> Self {
+        ReplayBackend { trace, position: 0 }
+    }
+
+    /// Whether every recorded command has been replayed.
+    pub fn is_finished(&self) -> bool {
+        self.position == self.trace.entries.len()
+    }
+}
+
+impl RobotBackend for ReplayBackend {
+    fn execute(&mut self, command: &Node) -> Result<Outcome, ExecutionError> {
+        let Some(entry) = self.trace.entries.get(self.position) else {
+            return Err(ExecutionError::from_node(
+                format!("Trace ended before command: {}", command.text),
+                command,
+            ));
+        };
+        if entry.command != command.text {
+            return Err(ExecutionError::from_node(
+                format!(
+                    "Replay diverged at command {}: expected '{}', got '{}'",
+                    self.position + 1,
+                    entry.command,
+                    command.text
+                ),
+                command,
+            ));
+        }
+        self.position += 1;
+        Ok(entry.outcome.clone())
+    }
+}
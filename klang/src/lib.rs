@@ -1,16 +1,35 @@
+pub mod executor;
 pub mod parser;
+pub mod project;
+pub mod repl;
+pub mod testing;
+pub mod watch;
 
 use crate::parser::errors::ParseError;
-use crate::parser::{parse_file, write_program_to_file};
+use crate::parser::schema::PrimitiveSchema;
+use crate::parser::{parse_file_with_schema, write_program_to_file, OutputFormat};
 use std::path::Path;
 
-pub fn compile_file(file_path: &Path, output_path: &Path, binary: bool) -> Result<(), ParseError> {
-    match parse_file(file_path) {
-        Ok(program) => write_program_to_file(&program, output_path, binary),
+pub fn compile_file(
+    file_path: &Path,
+    output_path: &Path,
+    format: OutputFormat,
+) -> Result<(), ParseError> {
+    compile_file_with_schema(file_path, output_path, format, None)
+}
+
+pub fn compile_file_with_schema(
+    file_path: &Path,
+    output_path: &Path,
+    format: OutputFormat,
+    schema: Option<&PrimitiveSchema>,
+) -> Result<(), ParseError> {
+    match parse_file_with_schema(file_path, schema) {
+        Ok(program) => write_program_to_file(&program, output_path, format),
         Err(e) => Err(e),
     }
 }
 
-pub fn compile_file_inplace(file_path: &Path, binary: bool) -> Result<(), ParseError> {
-    compile_file(file_path, file_path.with_extension("ko").as_path(), binary)
+pub fn compile_file_inplace(file_path: &Path, format: OutputFormat) -> Result<(), ParseError> {
+    compile_file(file_path, file_path.with_extension("ko").as_path(), format)
 }
@@ -0,0 +1,318 @@
+use super::cst::{Span, SyntaxElement, SyntaxKind, SyntaxNode, SyntaxTree};
+use super::errors::ParseError;
+use super::ir::{text_part::PartKind, Program, TextWithArgs};
+use super::lang::{doc_comment, parse_ir, parse_program, parse_tests, text_with_args};
+use super::passes::{expand_call, get_function_signature, resolve_call};
+use super::robot::RobotDescription;
+use super::schema::PrimitiveSchema;
+use super::structs::{Node, SourceLocation};
+use std::collections::HashMap;
+
+/// An error in the source, at the line and column it was reported at.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub location: SourceLocation,
+}
+
+#[derive(Clone, Debug)]
+pub struct Definition {
+    pub signature: String,
+    pub params: Vec<String>,
+    /// The `///` comments above the definition.
+    pub doc: String,
+    /// The whole definition, from `>` to `}`.
+    pub span: Span,
+    pub name_span: Span,
+    /// The definition this one is nested in.
+    pub parent: Option<usize>,
+    pub(super) name: TextWithArgs,
+}
+
+#[derive(Clone, Debug)]
+pub struct Call {
+    pub text: String,
+    pub span: Span,
+    pub name_span: Span,
+    /// The definition the call resolves to.
+    pub definition: Option<usize>,
+    /// The definition whose body contains the call.
+    pub caller: Option<usize>,
+    pub(super) name: TextWithArgs,
+}
+
+/// A function signature to insert at a call, with a snippet placeholder for
+/// each parameter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Completion {
+    pub label: String,
+    pub snippet: String,
+    pub doc: String,
+}
+
+/// Definitions, calls and diagnostics of one source file, for editor
+/// tooling. The tree is missing if the source does not parse.
+pub struct Analysis {
+    pub tree: Option<SyntaxTree>,
+    pub definitions: Vec<Definition>,
+    pub calls: Vec<Call>,
+    pub diagnostics: Vec<Diagnostic>,
+    ir: Program,
+}
+
+impl Analysis {
+    pub fn new(source: &str) -> Self {
+        Self::with_checks(source, None, None)
+    }
+
+    /// Analyses the source, also reporting commands that are not in the
+    /// schema and joint moves outside the robot's limits.
+    pub fn with_checks(
+        source: &str,
+        schema: Option<&PrimitiveSchema>,
+        robot: Option<&RobotDescription>,
+    ) -> Self {
+        let mut analysis = Analysis {
+            tree: None,
+            definitions: Vec::new(),
+            calls: Vec::new(),
+            diagnostics: Vec::new(),
+            ir: Program::default(),
+        };
+        let tree = match SyntaxTree::parse(source) {
+            Ok(tree) => tree,
+            Err(e) => {
+                analysis.diagnostics = diagnostics(&e);
+                return analysis;
+            }
+        };
+        match parse_ir(&tree) {
+            Ok(ir) => analysis.ir = ir,
+            Err(e) => analysis.diagnostics = diagnostics(&e),
+        }
+        analysis.collect(&tree, &tree.root, None);
+        analysis.resolve(&tree);
+        if analysis.diagnostics.is_empty() {
+            let checked = parse_program(&tree, schema)
+                .and_then(|program| match robot {
+                    Some(robot) => program.check_safety(robot),
+                    None => Ok(()),
+                })
+                .and_then(|_| parse_tests(&tree, schema).map(|_| ()));
+            if let Err(e) = checked {
+                analysis.diagnostics = diagnostics(&e);
+            }
+        }
+        analysis.tree = Some(tree);
+        analysis
+    }
+
+    fn collect(&mut self, tree: &SyntaxTree, parent: &SyntaxNode, caller: Option<usize>) {
+        for (index, child) in parent.children.iter().enumerate() {
+            let SyntaxElement::Node(node) = child else {
+                continue;
+            };
+            if node.kind == SyntaxKind::TestBlock {
+                self.collect(tree, node, None);
+                continue;
+            }
+            let Some(name) = node.child(SyntaxKind::Name) else {
+                continue;
+            };
+            let name_span = trimmed(tree, name.span);
+            match node.kind {
+                SyntaxKind::FunctionDef => {
+                    let name = text_with_args(name);
+                    let (signature, params) = get_function_signature(&name);
+                    self.definitions.push(Definition {
+                        signature,
+                        params,
+                        doc: doc_comment(parent, index),
+                        span: node.span,
+                        name_span,
+                        parent: caller,
+                        name,
+                    });
+                    self.collect(tree, node, Some(self.definitions.len() - 1));
+                }
+                SyntaxKind::FunctionCall => self.calls.push(Call {
+                    text: tree.source[name_span.start..name_span.end].to_string(),
+                    span: node.span,
+                    name_span,
+                    definition: None,
+                    caller,
+                    name: text_with_args(name),
+                }),
+                _ => {}
+            }
+        }
+    }
+
+    fn resolve(&mut self, tree: &SyntaxTree) {
+        for call in &mut self.calls {
+            let definitions = self
+                .definitions
+                .iter()
+                .enumerate()
+                .map(|(i, definition)| (&definition.name, i));
+            call.definition = resolve_call(&call.name, definitions, &HashMap::new())
+                .map(|(definition, _)| definition);
+            if call.definition.is_none() {
+                let (line, column) = tree.line_col(call.span.start);
+                self.diagnostics.push(Diagnostic {
+                    message: format!("Function not found: {}", call.text),
+                    location: SourceLocation { line, column },
+                });
+            }
+        }
+    }
+
+    /// The call whose text is under the cursor.
+    pub fn call_at(&self, offset: usize) -> Option<&Call> {
+        self.calls.iter().find(|call| call.span.contains(offset))
+    }
+
+    /// The definition named under the cursor, either at a call to it or at
+    /// its own signature.
+    pub fn definition_at(&self, offset: usize) -> Option<usize> {
+        match self.call_at(offset) {
+            Some(call) => call.definition,
+            None => self
+                .definitions
+                .iter()
+                .position(|definition| definition.name_span.contains(offset)),
+        }
+    }
+
+    /// Every call that resolves to the definition.
+    pub fn references(&self, definition: usize) -> impl Iterator<Item = &Call> {
+        self.calls
+            .iter()
+            .filter(move |call| call.definition == Some(definition))
+    }
+
+    /// The commands the call under the cursor expands to, or for a
+    /// signature, its body.
+    pub fn expansion_at(&self, offset: usize) -> Option<String> {
+        match self.call_at(offset) {
+            Some(call) => self.expansion(&call.name, call.caller),
+            None => {
+                let definition = self.definition_at(offset)?;
+                self.expansion(&self.definitions[definition].name, Some(definition))
+            }
+        }
+    }
+
+    /// The commands of a call to the definition: its first call in the
+    /// program, or with no calls, its body.
+    pub fn example(&self, definition: usize) -> Option<String> {
+        match self.references(definition).next() {
+            Some(call) => self.expansion(&call.name, call.caller),
+            None => self.expansion(&self.definitions[definition].name, Some(definition)),
+        }
+    }
+
+    /// Expands a call made inside `scope`, leaving the parameters that are
+    /// not bound by the call in brackets.
+    fn expansion(&self, name: &TextWithArgs, mut scope: Option<usize>) -> Option<String> {
+        let mut params = HashMap::new();
+        while let Some(definition) = scope {
+            for param in &self.definitions[definition].params {
+                params
+                    .entry(param.clone())
+                    .or_insert_with(|| format!("[{}]", param));
+            }
+            scope = self.definitions[definition].parent;
+        }
+        let commands = expand_call(&self.ir, name, &params).ok()?;
+        let nodes: Vec<Node> = commands.iter().map(Node::from_ast).collect();
+        let mut result = String::new();
+        write_nodes(&nodes, 0, &mut result);
+        Some(result)
+    }
+
+    pub fn completions(&self) -> Vec<Completion> {
+        self.definitions
+            .iter()
+            .map(|definition| {
+                let mut placeholder = 0;
+                let parts: Vec<String> = definition
+                    .name
+                    .parts
+                    .iter()
+                    .filter_map(|part| match part.part_kind.as_ref()? {
+                        PartKind::Text(text) => Some(text.clone()),
+                        PartKind::FunctionArg(arg) => {
+                            placeholder += 1;
+                            Some(format!("${{{}:{}}}", placeholder, arg.text))
+                        }
+                    })
+                    .collect();
+                Completion {
+                    label: definition.signature.clone(),
+                    snippet: parts.join(" "),
+                    doc: definition.doc.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The signature and span of each definition that is not nested in another.
+pub(crate) fn top_level_definitions(source: &str) -> Result<Vec<(String, Span)>, ParseError> {
+    let analysis = Analysis::new(source);
+    if analysis.tree.is_none() {
+        let diagnostic = &analysis.diagnostics[0];
+        return Err(ParseError::at(
+            diagnostic.message.clone(),
+            diagnostic.location.line,
+            diagnostic.location.column,
+        ));
+    }
+    Ok(analysis
+        .definitions
+        .into_iter()
+        .filter(|definition| definition.parent.is_none())
+        .map(|definition| (definition.signature, definition.span))
+        .collect())
+}
+
+/// The span without the whitespace at either end.
+pub(super) fn trimmed(tree: &SyntaxTree, span: Span) -> Span {
+    let text = &tree.source[span.start..span.end];
+    let start = span.start + text.len() - text.trim_start().len();
+    Span {
+        start,
+        end: start.max(span.end - (text.len() - text.trim_end().len())),
+    }
+}
+
+pub(crate) fn write_nodes(nodes: &[Node], depth: usize, result: &mut String) {
+    let indent = "    ".repeat(depth);
+    for node in nodes {
+        result.push_str(&indent);
+        result.push_str(&node.text);
+        if !node.children.is_empty() {
+            result.push_str(" {\n");
+            write_nodes(&node.children, depth + 1, result);
+            result.push_str(&indent);
+            result.push('}');
+        }
+        result.push('\n');
+    }
+}
+
+/// One diagnostic per error. Errors without a location are reported at the
+/// start of the file.
+fn diagnostics(error: &ParseError) -> Vec<Diagnostic> {
+    error
+        .errors
+        .iter()
+        .map(|error| Diagnostic {
+            message: error.message.clone(),
+            location: error
+                .location
+                .unwrap_or(SourceLocation { line: 1, column: 1 }),
+        })
+        .collect()
+}
@@ -0,0 +1,309 @@
+use super::errors::ParseError;
+use super::structs::{PestParser, Rule};
+use pest::error::LineColLocation;
+use pest::iterators::Pair;
+use pest::Parser;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyntaxKind {
+    /// The whole source file.
+    Program,
+    /// `> signature { ... }`
+    FunctionDef,
+    /// `" name [argument]`
+    FunctionCall,
+    Command,
+    /// `test "name" { ... }`
+    TestBlock,
+    /// `expect commands { ... }` or `expect joint "key" is 90`, in a test.
+    Expect,
+    /// The words and bracketed arguments of a command, call or signature.
+    Name,
+    /// `[text]`, a parameter in a signature or an argument in a call.
+    Argument,
+    /// A run of words, including the spaces between them.
+    Text,
+    /// A word of test syntax, such as `test` or `expect`.
+    Keyword,
+    /// `"text"`, quotes included.
+    String,
+    Number,
+    Whitespace,
+    Newline,
+    LineComment,
+    /// A `///` comment documenting the definition below it.
+    DocComment,
+    BlockComment,
+    Gt,
+    Quote,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+}
+
+impl SyntaxKind {
+    /// Whether the token carries no meaning for the program.
+    pub fn is_trivia(self) -> bool {
+        matches!(
+            self,
+            SyntaxKind::Whitespace
+                | SyntaxKind::Newline
+                | SyntaxKind::LineComment
+                | SyntaxKind::DocComment
+                | SyntaxKind::BlockComment
+        )
+    }
+
+    pub fn is_comment(self) -> bool {
+        matches!(
+            self,
+            SyntaxKind::LineComment | SyntaxKind::DocComment | SyntaxKind::BlockComment
+        )
+    }
+}
+
+/// Byte offsets into the source, from `start` up to but not including `end`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Whether a cursor at `offset` touches the span, counting its end.
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset <= self.end
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntaxToken {
+    pub kind: SyntaxKind,
+    pub span: Span,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxElement {
+    pub fn span(&self) -> Span {
+        match self {
+            SyntaxElement::Node(node) => node.span,
+            SyntaxElement::Token(token) => token.span,
+        }
+    }
+}
+
+/// A node of the concrete syntax tree. Its children cover every byte of its
+/// span, trivia included, so the source can be printed back exactly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntaxNode {
+    pub kind: SyntaxKind,
+    pub span: Span,
+    pub children: Vec<SyntaxElement>,
+}
+
+impl SyntaxNode {
+    pub fn nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    pub fn tokens(&self) -> impl Iterator<Item = &SyntaxToken> {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Token(token) => Some(token),
+            SyntaxElement::Node(_) => None,
+        })
+    }
+
+    pub fn child(&self, kind: SyntaxKind) -> Option<&SyntaxNode> {
+        self.nodes().find(|node| node.kind == kind)
+    }
+
+    /// Every token under this node, in source order.
+    pub fn descendant_tokens(&self) -> Vec<&SyntaxToken> {
+        let mut tokens = Vec::new();
+        self.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn collect_tokens<'a>(&'a self, tokens: &mut Vec<&'a SyntaxToken>) {
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => node.collect_tokens(tokens),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for token in self.descendant_tokens() {
+            f.write_str(&token.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// A lossless parse of a source file.
+#[derive(Clone, Debug)]
+pub struct SyntaxTree {
+    pub source: String,
+    pub root: SyntaxNode,
+    line_starts: Vec<usize>,
+}
+
+impl SyntaxTree {
+    pub fn parse(input: &str) -> Result<Self, ParseError> {
+        let pair = match PestParser::parse(Rule::program, input) {
+            Ok(mut pairs) => pairs.next().unwrap(),
+            Err(e) => {
+                let (line, column) = match e.line_col {
+                    LineColLocation::Pos(position) | LineColLocation::Span(position, _) => position,
+                };
+                let message = format!("Error parsing input: {}", e.variant.message());
+                return Err(ParseError::at(message, line, column));
+            }
+        };
+        let mut children = Vec::new();
+        lex_gap(input, 0, pair.as_span().start(), &mut children);
+        let end = pair.as_span().end();
+        push_children(pair, input, &mut children);
+        lex_gap(input, end, input.len(), &mut children);
+        let root = SyntaxNode {
+            kind: SyntaxKind::Program,
+            span: Span {
+                start: 0,
+                end: input.len(),
+            },
+            children,
+        };
+        let line_starts = std::iter::once(0)
+            .chain(input.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Ok(SyntaxTree {
+            source: input.to_string(),
+            root,
+            line_starts,
+        })
+    }
+
+    /// The one-based line and column (in characters) of a byte offset.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|start| *start <= offset) - 1;
+        let column = self.source[self.line_starts[line]..offset].chars().count();
+        (line + 1, column + 1)
+    }
+}
+
+fn token(kind: SyntaxKind, source: &str, start: usize, end: usize) -> SyntaxElement {
+    SyntaxElement::Token(SyntaxToken {
+        kind,
+        span: Span { start, end },
+        text: source[start..end].to_string(),
+    })
+}
+
+/// Splits source between parsed rules into punctuation and whitespace.
+fn lex_gap(source: &str, start: usize, end: usize, out: &mut Vec<SyntaxElement>) {
+    let mut position = start;
+    while position < end {
+        let rest = &source[position..end];
+        let c = rest.chars().next().unwrap();
+        let (kind, length) = match c {
+            ' ' | '\t' => (
+                SyntaxKind::Whitespace,
+                rest.find(|c| c != ' ' && c != '\t').unwrap_or(rest.len()),
+            ),
+            '\r' if rest.starts_with("\r\n") => (SyntaxKind::Newline, 2),
+            '\n' => (SyntaxKind::Newline, 1),
+            '>' => (SyntaxKind::Gt, 1),
+            '"' => (SyntaxKind::Quote, 1),
+            '{' => (SyntaxKind::LBrace, 1),
+            '}' => (SyntaxKind::RBrace, 1),
+            '[' => (SyntaxKind::LBracket, 1),
+            ']' => (SyntaxKind::RBracket, 1),
+            c if c.is_ascii_alphabetic() => (
+                SyntaxKind::Keyword,
+                rest.find(|c: char| !c.is_ascii_alphabetic())
+                    .unwrap_or(rest.len()),
+            ),
+            _ => (SyntaxKind::Text, c.len_utf8()),
+        };
+        out.push(token(kind, source, position, position + length));
+        position += length;
+    }
+}
+
+fn push_children(pair: Pair<Rule>, source: &str, out: &mut Vec<SyntaxElement>) {
+    let mut position = pair.as_span().start();
+    let end = pair.as_span().end();
+    for child in pair.into_inner() {
+        let span = child.as_span();
+        lex_gap(source, position, span.start(), out);
+        position = span.end();
+        push_pair(child, source, out);
+    }
+    lex_gap(source, position, end, out);
+}
+
+fn push_pair(pair: Pair<Rule>, source: &str, out: &mut Vec<SyntaxElement>) {
+    let span = pair.as_span();
+    let (start, end) = (span.start(), span.end());
+    let kind = match pair.as_rule() {
+        Rule::function_def => SyntaxKind::FunctionDef,
+        Rule::function_call => SyntaxKind::FunctionCall,
+        Rule::command => SyntaxKind::Command,
+        Rule::test_block => SyntaxKind::TestBlock,
+        Rule::expect => SyntaxKind::Expect,
+        Rule::text_with_function_args | Rule::text_with_function_params => SyntaxKind::Name,
+        Rule::function_arg | Rule::function_param => SyntaxKind::Argument,
+        Rule::text | Rule::string | Rule::number => {
+            let kind = match pair.as_rule() {
+                Rule::string => SyntaxKind::String,
+                Rule::number => SyntaxKind::Number,
+                _ => SyntaxKind::Text,
+            };
+            out.push(token(kind, source, start, end));
+            return;
+        }
+        Rule::COMMENT => {
+            let text = pair.as_str();
+            if text.starts_with("//") {
+                let comment_end = start + text.trim_end_matches(['\r', '\n']).len();
+                let kind = if text.starts_with("///") && !text.starts_with("////") {
+                    SyntaxKind::DocComment
+                } else {
+                    SyntaxKind::LineComment
+                };
+                out.push(token(kind, source, start, comment_end));
+                lex_gap(source, comment_end, end, out);
+            } else {
+                out.push(token(SyntaxKind::BlockComment, source, start, end));
+            }
+            return;
+        }
+        // Lines, empty lines and the parts of expectations only group their
+        // contents.
+        _ => {
+            push_children(pair, source, out);
+            return;
+        }
+    };
+    let mut children = Vec::new();
+    push_children(pair, source, &mut children);
+    out.push(SyntaxElement::Node(SyntaxNode {
+        kind,
+        span: Span { start, end },
+        children,
+    }));
+}
@@ -0,0 +1,290 @@
+use super::errors::ParseError;
+use super::format::write_program;
+use super::ir::{
+    line::LineKind, text_part::PartKind, Command, Function, FunctionArg, FunctionCall, Line,
+    Program, TextPart, TextWithArgs,
+};
+use super::structs::{KlangProgram, Node};
+
+/// Parameter values visible inside a function call, innermost first.
+type Scope = Vec<(String, String)>;
+
+/// Every expansion of one function in the program, with the scope its body
+/// was expanded in.
+struct Calls<'a> {
+    signature: String,
+    instances: Vec<(&'a Node, Scope)>,
+}
+
+fn collect_calls<'a>(nodes: &'a [Node], scope: &Scope, calls: &mut Vec<Calls<'a>>) {
+    for node in nodes {
+        let Some(signature) = &node.function else {
+            continue;
+        };
+        let mut inner = node.arguments.clone();
+        inner.extend(
+            scope
+                .iter()
+                .filter(|(name, _)| !node.arguments.iter().any(|(own, _)| own == name))
+                .cloned(),
+        );
+        match calls.iter_mut().find(|calls| calls.signature == *signature) {
+            Some(calls) => calls.instances.push((node, inner.clone())),
+            None => calls.push(Calls {
+                signature: signature.clone(),
+                instances: vec![(node, inner.clone())],
+            }),
+        }
+        collect_calls(&node.children, &inner, calls);
+    }
+}
+
+fn text_part(text: String) -> TextPart {
+    TextPart {
+        part_kind: Some(PartKind::Text(text)),
+    }
+}
+
+fn arg_part(text: String) -> TextPart {
+    TextPart {
+        part_kind: Some(PartKind::FunctionArg(FunctionArg { text })),
+    }
+}
+
+/// Splits a signature such as `wave [arm] arm` into its words and
+/// parameters.
+fn signature_parts(signature: &str) -> Vec<TextPart> {
+    let mut parts = Vec::new();
+    let mut rest = signature;
+    while let Some(start) = rest.find('[') {
+        let end = rest[start..]
+            .find(']')
+            .map_or(rest.len(), |end| start + end);
+        if !rest[..start].trim().is_empty() {
+            parts.push(text_part(rest[..start].trim().to_string()));
+        }
+        parts.push(arg_part(rest[start + 1..end].to_string()));
+        rest = rest.get(end + 1..).unwrap_or_default();
+    }
+    if !rest.trim().is_empty() {
+        parts.push(text_part(rest.trim().to_string()));
+    }
+    parts
+}
+
+fn push_word(parts: &mut Vec<TextPart>, word: &str) {
+    if let Some(TextPart {
+        part_kind: Some(PartKind::Text(text)),
+    }) = parts.last_mut()
+    {
+        text.push(' ');
+        text.push_str(word);
+        return;
+    }
+    parts.push(text_part(word.to_string()));
+}
+
+fn lookup<'a>(scope: &'a Scope, name: &str) -> Option<&'a str> {
+    scope
+        .iter()
+        .find(|(param, _)| param == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Rebuilds the text of a command in a function body, replacing words with
+/// the parameters that produced them in every expansion.
+fn command_template(texts: &[(&str, &Scope)], signature: &str) -> Result<TextWithArgs, ParseError> {
+    let words: Vec<Vec<&str>> = texts
+        .iter()
+        .map(|(text, _)| text.split_whitespace().collect())
+        .collect();
+    let mut cursors = vec![0; texts.len()];
+    let mut parts = Vec::new();
+    let (first, scope) = texts[0];
+    while cursors[0] < words[0].len() {
+        let param = scope.iter().find_map(|(name, _)| {
+            let lengths = texts
+                .iter()
+                .zip(&words)
+                .zip(&cursors)
+                .map(|(((_, scope), words), &cursor)| {
+                    let value: Vec<&str> = lookup(scope, name)?.split_whitespace().collect();
+                    let matches = !value.is_empty() && words[cursor..].starts_with(&value);
+                    matches.then_some(value.len())
+                })
+                .collect::<Option<Vec<usize>>>()?;
+            Some((name, lengths))
+        });
+        if let Some((name, lengths)) = param {
+            parts.push(arg_part(name.clone()));
+            for (cursor, length) in cursors.iter_mut().zip(lengths) {
+                *cursor += length;
+            }
+            continue;
+        }
+        let word = words[0][cursors[0]];
+        if !words
+            .iter()
+            .zip(&cursors)
+            .all(|(words, &cursor)| words.get(cursor) == Some(&word))
+        {
+            return Err(ParseError::new(format!(
+                "Cannot decompile '{}' in function '{}': its expansions differ",
+                first, signature
+            )));
+        }
+        push_word(&mut parts, word);
+        for cursor in cursors.iter_mut() {
+            *cursor += 1;
+        }
+    }
+    if words
+        .iter()
+        .zip(&cursors)
+        .any(|(words, &cursor)| cursor != words.len())
+    {
+        return Err(ParseError::new(format!(
+            "Cannot decompile '{}' in function '{}': its expansions differ",
+            first, signature
+        )));
+    }
+    Ok(TextWithArgs { parts })
+}
+
+/// Rebuilds a function call, passing a parameter of the caller where it
+/// produced the argument in every expansion.
+fn call_template(calls: &[(&Node, &Scope)], signature: &str) -> Result<TextWithArgs, ParseError> {
+    let (first, _) = calls[0];
+    let callee = first.function.as_deref().unwrap_or_default();
+    let mut parts = Vec::new();
+    for part in signature_parts(callee) {
+        let Some(PartKind::FunctionArg(param)) = &part.part_kind else {
+            parts.push(part);
+            continue;
+        };
+        let values: Vec<(&str, &Scope)> = calls
+            .iter()
+            .map(|(node, scope)| {
+                (
+                    lookup(&node.arguments, &param.text).unwrap_or_default(),
+                    *scope,
+                )
+            })
+            .collect();
+        let (value, scope) = values[0];
+        let passed = scope.iter().find(|(name, _)| {
+            values
+                .iter()
+                .all(|(value, scope)| lookup(scope, name) == Some(value))
+        });
+        match passed {
+            Some((name, _)) => parts.push(arg_part(name.clone())),
+            None if values.iter().all(|(other, _)| *other == value) => {
+                parts.push(arg_part(value.to_string()))
+            }
+            None => {
+                return Err(ParseError::new(format!(
+                    "Cannot decompile call '{}' in function '{}': its expansions differ",
+                    first.text, signature
+                )))
+            }
+        }
+    }
+    Ok(TextWithArgs { parts })
+}
+
+fn function_body(calls: &Calls) -> Result<Vec<Line>, ParseError> {
+    let (first, _) = &calls.instances[0];
+    let mut lines = Vec::new();
+    for (i, child) in first.children.iter().enumerate() {
+        let expansions = calls
+            .instances
+            .iter()
+            .map(|(node, scope)| {
+                let other = node
+                    .children
+                    .get(i)
+                    .filter(|other| other.function == child.function)?;
+                Some((other, scope))
+            })
+            .collect::<Option<Vec<_>>>()
+            .filter(|_| {
+                calls
+                    .instances
+                    .iter()
+                    .all(|(node, _)| node.children.len() == first.children.len())
+            })
+            .ok_or_else(|| {
+                ParseError::new(format!(
+                    "Cannot decompile function '{}': its expansions differ",
+                    calls.signature
+                ))
+            })?;
+        let line_kind = if child.is_function() {
+            LineKind::FunctionCall(FunctionCall {
+                name: Some(call_template(&expansions, &calls.signature)?),
+            })
+        } else {
+            let texts: Vec<(&str, &Scope)> = expansions
+                .iter()
+                .map(|(node, scope)| (node.text.as_str(), *scope))
+                .collect();
+            LineKind::Command(Command {
+                text: Some(command_template(&texts, &calls.signature)?),
+            })
+        };
+        lines.push(Line {
+            line_kind: Some(line_kind),
+            location: None,
+        });
+    }
+    Ok(lines)
+}
+
+impl KlangProgram {
+    /// Reconstructs source code that compiles to this program. Each function
+    /// is defined once at the top level, with its body recovered by matching
+    /// the words of each expansion against the bound arguments.
+    pub fn decompile(&self) -> Result<String, ParseError> {
+        let mut calls = Vec::new();
+        collect_calls(&self.program, &Vec::new(), &mut calls);
+
+        let mut lines = Vec::new();
+        for calls in &calls {
+            lines.push(Line {
+                line_kind: Some(LineKind::Function(Function {
+                    name: Some(TextWithArgs {
+                        parts: signature_parts(&calls.signature),
+                    }),
+                    lines: function_body(calls)?,
+                    ..Default::default()
+                })),
+                location: None,
+            });
+        }
+        let empty = Vec::new();
+        for node in &self.program {
+            let line_kind = if node.is_function() {
+                LineKind::FunctionCall(FunctionCall {
+                    name: Some(call_template(&[(node, &empty)], "")?),
+                })
+            } else {
+                let mut parts = Vec::new();
+                for word in node.text.split_whitespace() {
+                    push_word(&mut parts, word);
+                }
+                LineKind::Command(Command {
+                    text: Some(TextWithArgs { parts }),
+                })
+            };
+            lines.push(Line {
+                line_kind: Some(line_kind),
+                location: None,
+            });
+        }
+        Ok(write_program(&Program {
+            lines,
+            ..Default::default()
+        }))
+    }
+}
@@ -0,0 +1,179 @@
+use super::analysis::Analysis;
+use super::errors::ParseError;
+use super::visualize::escape_html;
+use std::fs;
+use std::path::Path;
+
+/// The reference entry for one function definition.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionDoc {
+    pub signature: String,
+    pub params: Vec<String>,
+    pub doc: String,
+    /// Signatures of the functions that call this one, in source order.
+    pub callers: Vec<String>,
+    /// Calls made from the top level of the program.
+    pub top_level_calls: usize,
+    /// The commands of the first call, or the body if it is never called.
+    pub example: Option<String>,
+}
+
+impl FunctionDoc {
+    pub fn from_analysis(analysis: &Analysis) -> Vec<FunctionDoc> {
+        (0..analysis.definitions.len())
+            .map(|i| {
+                let definition = &analysis.definitions[i];
+                let mut callers = Vec::new();
+                let mut top_level_calls = 0;
+                for call in analysis.references(i) {
+                    match call.caller {
+                        Some(caller) => {
+                            let signature = &analysis.definitions[caller].signature;
+                            if !callers.contains(signature) {
+                                callers.push(signature.clone());
+                            }
+                        }
+                        None => top_level_calls += 1,
+                    }
+                }
+                FunctionDoc {
+                    signature: definition.signature.clone(),
+                    params: definition.params.clone(),
+                    doc: definition.doc.clone(),
+                    callers,
+                    top_level_calls,
+                    example: analysis.example(i),
+                }
+            })
+            .collect()
+    }
+
+    /// The callers rendered by `code`, then the program itself if it calls
+    /// the function.
+    fn called_by(&self, code: impl Fn(&str) -> String) -> Vec<String> {
+        let mut callers: Vec<String> = self.callers.iter().map(|caller| code(caller)).collect();
+        if self.top_level_calls > 0 {
+            callers.push("the program".to_string());
+        }
+        callers
+    }
+}
+
+/// A page of reference documentation, with a section of functions for each
+/// source file.
+pub struct Reference {
+    pub title: String,
+    pub sections: Vec<(String, Vec<FunctionDoc>)>,
+}
+
+impl Reference {
+    pub fn new(title: &str) -> Self {
+        Reference {
+            title: title.to_string(),
+            sections: Vec::new(),
+        }
+    }
+
+    pub fn add_source(&mut self, name: &str, source: &str) -> Result<(), ParseError> {
+        let analysis = Analysis::new(source);
+        if analysis.tree.is_none() {
+            let diagnostic = &analysis.diagnostics[0];
+            return Err(ParseError::at(
+                diagnostic.message.clone(),
+                diagnostic.location.line,
+                diagnostic.location.column,
+            ));
+        }
+        self.sections
+            .push((name.to_string(), FunctionDoc::from_analysis(&analysis)));
+        Ok(())
+    }
+
+    pub fn add_file(&mut self, path: &Path) -> Result<(), ParseError> {
+        let source = fs::read_to_string(path)?;
+        self.add_source(&path.display().to_string(), &source)
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut result = format!("# {}\n", self.title);
+        for (name, functions) in &self.sections {
+            result.push_str(&format!("\n## {}\n", name));
+            for function in functions {
+                result.push_str(&format!("\n### `{}`\n\n", function.signature));
+                if !function.doc.is_empty() {
+                    result.push_str(&format!("{}\n\n", function.doc));
+                }
+                if !function.params.is_empty() {
+                    let params: Vec<String> = function
+                        .params
+                        .iter()
+                        .map(|param| format!("`{}`", param))
+                        .collect();
+                    result.push_str(&format!("Parameters: {}\n\n", params.join(", ")));
+                }
+                let callers = function.called_by(|caller| format!("`{}`", caller));
+                if callers.is_empty() {
+                    result.push_str("Not called.\n");
+                } else {
+                    result.push_str(&format!("Called by: {}\n", callers.join(", ")));
+                }
+                if let Some(example) = &function.example {
+                    result.push_str(&format!("\nExample:\n\n```\n{}```\n", example));
+                }
+            }
+        }
+        result
+    }
+
+    pub fn to_html(&self) -> String {
+        let title = escape_html(&self.title);
+        let mut result = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+            title, HTML_STYLE, title
+        );
+        for (name, functions) in &self.sections {
+            result.push_str(&format!("<h2>{}</h2>\n", escape_html(name)));
+            for function in functions {
+                result.push_str(&format!(
+                    "<section>\n<h3><code>{}</code></h3>\n",
+                    escape_html(&function.signature)
+                ));
+                for paragraph in function.doc.split("\n\n").filter(|p| !p.is_empty()) {
+                    result.push_str(&format!("<p>{}</p>\n", escape_html(paragraph)));
+                }
+                if !function.params.is_empty() {
+                    let params: Vec<String> = function
+                        .params
+                        .iter()
+                        .map(|param| format!("<code>{}</code>", escape_html(param)))
+                        .collect();
+                    result.push_str(&format!(
+                        "<p class=\"label\">Parameters: {}</p>\n",
+                        params.join(", ")
+                    ));
+                }
+                let callers =
+                    function.called_by(|caller| format!("<code>{}</code>", escape_html(caller)));
+                if callers.is_empty() {
+                    result.push_str("<p class=\"label\">Not called.</p>\n");
+                } else {
+                    result.push_str(&format!(
+                        "<p class=\"label\">Called by: {}</p>\n",
+                        callers.join(", ")
+                    ));
+                }
+                if let Some(example) = &function.example {
+                    result.push_str(&format!("<pre>{}</pre>\n", escape_html(example)));
+                }
+                result.push_str("</section>\n");
+            }
+        }
+        result.push_str("</body>\n</html>\n");
+        result
+    }
+}
+
+const HTML_STYLE: &str = "body { font-family: sans-serif; max-width: 50em; margin: auto; }
+section { border-top: 1px solid #ddd; }
+.label { color: #555; }
+pre { background: #f6f6f6; padding: 0.5em; }";
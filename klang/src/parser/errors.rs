@@ -1,23 +1,77 @@
-use crate::parser::Rule;
+use crate::parser::structs::{Rule, SourceLocation};
 use pest::iterators::Pair;
 use prost::{DecodeError, EncodeError};
 use std::error::Error;
 use std::fmt;
+use std::path::PathBuf;
+
+/// One error, and where in the source it was found.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocatedError {
+    pub message: String,
+    pub location: Option<SourceLocation>,
+    /// The file the location is in, when it is not the file being compiled.
+    pub path: Option<PathBuf>,
+}
+
+impl fmt::Display for LocatedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.location, &self.path) {
+            (Some(location), Some(path)) => write!(
+                f,
+                "{} ({}, line: {}, column: {})",
+                self.message,
+                path.display(),
+                location.line,
+                location.column
+            ),
+            (Some(location), None) => write!(
+                f,
+                "{} (line: {}, column: {})",
+                self.message, location.line, location.column
+            ),
+            (None, _) => write!(f, "{}", self.message),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct ParseError {
+    /// Every error, one per line, each followed by where it was found.
     pub message: String,
+    /// The same errors, with their locations kept apart from the text.
+    pub errors: Vec<LocatedError>,
 }
 
 impl ParseError {
     pub fn new(message: String) -> ParseError {
-        ParseError { message }
+        ParseError::from_errors(vec![LocatedError {
+            message,
+            location: None,
+            path: None,
+        }])
     }
 
     pub fn from_pair(message: String, pair: Pair<Rule>) -> ParseError {
         let (line, column) = pair.as_span().start_pos().line_col();
+        ParseError::at(message, line, column)
+    }
+
+    pub fn at(message: String, line: usize, column: usize) -> ParseError {
+        ParseError::from_errors(vec![LocatedError {
+            message,
+            location: Some(SourceLocation { line, column }),
+            path: None,
+        }])
+    }
+
+    /// Reports several errors at once, such as every unknown command in a
+    /// program.
+    pub fn from_errors(errors: Vec<LocatedError>) -> ParseError {
+        let lines: Vec<String> = errors.iter().map(ToString::to_string).collect();
         ParseError {
-            message: format!("{} (line: {}, column: {})", message, line, column),
+            message: lines.join("\n"),
+            errors,
         }
     }
 }
@@ -47,3 +101,15 @@ impl From<DecodeError> for ParseError {
         ParseError::new(format!("{}", error))
     }
 }
+
+impl From<serde_json::Error> for ParseError {
+    fn from(error: serde_json::Error) -> Self {
+        ParseError::new(format!("{}", error))
+    }
+}
+
+impl From<serde_yaml::Error> for ParseError {
+    fn from(error: serde_yaml::Error) -> Self {
+        ParseError::new(format!("{}", error))
+    }
+}
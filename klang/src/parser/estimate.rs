@@ -0,0 +1,189 @@
+use super::errors::ParseError;
+use super::schema::{DurationEstimate, DurationSpec, PrimitiveSchema};
+use super::structs::{KlangProgram, Node};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Deserialize)]
+struct DurationFile {
+    default: Option<DurationSpec>,
+    #[serde(default)]
+    durations: HashMap<String, DurationSpec>,
+}
+
+/// Per-command duration estimates, keyed by primitive name or by command
+/// text.
+///
+/// The durations come from the `duration` of each schema primitive, or from
+/// a table such as:
+///
+/// ```toml
+/// default = 1.0
+///
+/// [durations]
+/// move_joint = { expected = 2.0, min = 1.5, max = 3.0 }
+/// wait = "seconds"
+/// "wave hello" = 0.5
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct DurationModel {
+    pub durations: HashMap<String, DurationSpec>,
+    /// Used for commands with no entry of their own.
+    pub default: Option<DurationEstimate>,
+}
+
+impl DurationModel {
+    pub fn from_schema(schema: &PrimitiveSchema) -> Self {
+        let durations = schema
+            .primitives
+            .iter()
+            .filter_map(|primitive| Some((primitive.name.clone(), primitive.duration.clone()?)))
+            .collect();
+        DurationModel {
+            durations,
+            default: None,
+        }
+    }
+
+    pub fn from_toml(input: &str) -> Result<Self, ParseError> {
+        let file: DurationFile = toml::from_str(input)
+            .map_err(|e| ParseError::new(format!("Error parsing duration table: {}", e)))?;
+        let default = match file.default {
+            Some(spec) => Some(spec.estimate(&Vec::new()).ok_or_else(|| {
+                ParseError::new("The default duration must be a number".to_string())
+            })?),
+            None => None,
+        };
+        Ok(DurationModel {
+            durations: file.durations,
+            default,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ParseError> {
+        let input = fs::read_to_string(path).map_err(|e| {
+            ParseError::new(format!(
+                "Error reading duration table '{}': {}",
+                path.display(),
+                e
+            ))
+        })?;
+        DurationModel::from_toml(&input)
+    }
+
+    /// Adds the entries of `other`, replacing any with the same key.
+    pub fn extend(&mut self, other: DurationModel) {
+        self.durations.extend(other.durations);
+        if other.default.is_some() {
+            self.default = other.default;
+        }
+    }
+
+    fn command_duration(&self, node: &Node) -> Option<DurationEstimate> {
+        let by_action = node.action.as_ref().and_then(|action| {
+            self.durations
+                .get(&action.name)?
+                .estimate(&action.arguments)
+        });
+        by_action
+            .or_else(|| self.durations.get(&node.text)?.estimate(&Vec::new()))
+            .or(self.default)
+    }
+}
+
+/// The time spent in all calls to one function, including the functions it
+/// calls.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionDuration {
+    pub function: String,
+    pub calls: usize,
+    pub duration: DurationEstimate,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DurationReport {
+    pub total: DurationEstimate,
+    /// Functions in the order they are first called.
+    pub functions: Vec<FunctionDuration>,
+    /// Commands with no duration estimate, which count as taking no time.
+    pub unknown: Vec<String>,
+}
+
+impl DurationReport {
+    /// Commands run one after another, so the duration of a sequence is the
+    /// sum of its parts.
+    fn estimate_nodes(&mut self, nodes: &[Node], model: &DurationModel) -> DurationEstimate {
+        nodes
+            .iter()
+            .map(|node| self.estimate_node(node, model))
+            .fold(DurationEstimate::default(), |total, duration| {
+                total + duration
+            })
+    }
+
+    fn estimate_node(&mut self, node: &Node, model: &DurationModel) -> DurationEstimate {
+        let Some(function) = &node.function else {
+            return model.command_duration(node).unwrap_or_else(|| {
+                if !self.unknown.contains(&node.text) {
+                    self.unknown.push(node.text.clone());
+                }
+                DurationEstimate::default()
+            });
+        };
+        let index = match self
+            .functions
+            .iter()
+            .position(|entry| entry.function == *function)
+        {
+            Some(index) => index,
+            None => {
+                self.functions.push(FunctionDuration {
+                    function: function.clone(),
+                    calls: 0,
+                    duration: DurationEstimate::default(),
+                });
+                self.functions.len() - 1
+            }
+        };
+        let duration = self.estimate_nodes(&node.children, model);
+        let entry = &mut self.functions[index];
+        entry.calls += 1;
+        entry.duration = entry.duration + duration;
+        duration
+    }
+}
+
+impl std::fmt::Display for DurationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Total: {}", self.total)?;
+        if !self.functions.is_empty() {
+            writeln!(f, "\nFunctions:")?;
+            for entry in &self.functions {
+                let calls = if entry.calls == 1 { "call" } else { "calls" };
+                writeln!(
+                    f,
+                    "  {} ({} {}): {}",
+                    entry.function, entry.calls, calls, entry.duration
+                )?;
+            }
+        }
+        if !self.unknown.is_empty() {
+            writeln!(f, "\nCommands without an estimate:")?;
+            for text in &self.unknown {
+                writeln!(f, "  {}", text)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl KlangProgram {
+    /// Estimates how long the program takes to run, without running it.
+    pub fn estimate_duration(&self, model: &DurationModel) -> DurationReport {
+        let mut report = DurationReport::default();
+        report.total = report.estimate_nodes(&self.program, model);
+        report
+    }
+}
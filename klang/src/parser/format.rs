@@ -0,0 +1,304 @@
+use super::cst::{Span, SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken, SyntaxTree};
+use super::ir::{line::LineKind, text_part::PartKind, Line, Program, TextWithArgs};
+
+const INDENT: &str = "    ";
+
+enum Syntax {
+    /// A function definition, test or `expect commands`, with everything
+    /// before the opening brace as its header.
+    Block {
+        header: String,
+        /// Comments on the same line as the opening brace.
+        header_comments: Vec<String>,
+        body: Vec<Item>,
+        /// Whether blank lines set the block apart from its neighbours.
+        set_apart: bool,
+    },
+    Call(String),
+    Command(String),
+    Comment(String),
+}
+
+/// A line of source with the comments that follow it on the same line.
+/// `start` and `end` are the source lines it spans, used to keep the blank
+/// lines the author wrote.
+struct Item {
+    syntax: Syntax,
+    start: usize,
+    end: usize,
+    comments: Vec<String>,
+}
+
+impl Item {
+    fn is_set_apart(&self) -> bool {
+        matches!(
+            self.syntax,
+            Syntax::Block {
+                set_apart: true,
+                ..
+            }
+        )
+    }
+
+    fn is_comment(&self) -> bool {
+        matches!(self.syntax, Syntax::Comment(_))
+    }
+}
+
+fn words(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn name_text(name: &SyntaxNode) -> String {
+    let parts: Vec<String> = name
+        .children
+        .iter()
+        .filter_map(|child| match child {
+            SyntaxElement::Token(token) if token.kind == SyntaxKind::Text => {
+                Some(words(&token.text))
+            }
+            SyntaxElement::Node(node) if node.kind == SyntaxKind::Argument => {
+                let text: String = node
+                    .tokens()
+                    .filter(|token| token.kind == SyntaxKind::Text)
+                    .map(|token| token.text.as_str())
+                    .collect();
+                Some(format!("[{}]", words(&text)))
+            }
+            _ => None,
+        })
+        .filter(|part| !part.is_empty())
+        .collect();
+    parts.join(" ")
+}
+
+/// The words of an `expect` statement, with the name of the state in quotes.
+fn keywords(node: &SyntaxNode) -> Vec<String> {
+    node.tokens()
+        .filter(|token| {
+            matches!(
+                token.kind,
+                SyntaxKind::Keyword | SyntaxKind::String | SyntaxKind::Number
+            )
+        })
+        .map(|token| token.text.clone())
+        .collect()
+}
+
+fn comment_text(token: &SyntaxToken) -> String {
+    token.text.trim_end().to_string()
+}
+
+fn start_line(tree: &SyntaxTree, span: Span) -> usize {
+    tree.line_col(span.start).0
+}
+
+fn end_line(tree: &SyntaxTree, span: Span) -> usize {
+    tree.line_col(span.end.max(span.start + 1) - 1).0
+}
+
+fn items_from_cst(children: &[SyntaxElement], tree: &SyntaxTree) -> Vec<Item> {
+    let mut items: Vec<Item> = Vec::new();
+    for child in children {
+        let node = match child {
+            SyntaxElement::Token(token) if token.kind.is_comment() => {
+                let (start, end) = (start_line(tree, token.span), end_line(tree, token.span));
+                match items.last_mut() {
+                    Some(last) if last.end == start => last.comments.push(comment_text(token)),
+                    _ => items.push(Item {
+                        syntax: Syntax::Comment(comment_text(token)),
+                        start,
+                        end,
+                        comments: Vec::new(),
+                    }),
+                }
+                continue;
+            }
+            SyntaxElement::Node(node) => node,
+            SyntaxElement::Token(_) => continue,
+        };
+
+        let (start, end) = (start_line(tree, node.span), end_line(tree, node.span));
+        let mut comments = Vec::new();
+        let syntax = match node.kind {
+            SyntaxKind::Expect if node.tokens().all(|token| token.kind != SyntaxKind::LBrace) => {
+                comments.extend(
+                    node.tokens()
+                        .filter(|token| token.kind.is_comment())
+                        .map(comment_text),
+                );
+                Syntax::Command(keywords(node).join(" "))
+            }
+            SyntaxKind::FunctionDef | SyntaxKind::TestBlock | SyntaxKind::Expect => {
+                let mut header_comments = Vec::new();
+                let mut header = Vec::new();
+                let mut rest = node.children.as_slice();
+                while let Some((child, tail)) = rest.split_first() {
+                    match child {
+                        SyntaxElement::Token(token) if token.kind.is_comment() => {
+                            header_comments.push(comment_text(token))
+                        }
+                        SyntaxElement::Token(token) if token.kind == SyntaxKind::LBrace => break,
+                        SyntaxElement::Token(token) if !token.kind.is_trivia() => {
+                            header.push(token.text.clone())
+                        }
+                        SyntaxElement::Node(name) if name.kind == SyntaxKind::Name => {
+                            header.push(name_text(name))
+                        }
+                        _ => {}
+                    }
+                    rest = tail;
+                }
+                // Comments after the opening brace, on the same line.
+                while let Some((SyntaxElement::Token(token), tail)) = rest.split_first() {
+                    if token.kind.is_comment() && start_line(tree, token.span) == start {
+                        header_comments.push(comment_text(token));
+                    } else if !matches!(token.kind, SyntaxKind::Whitespace | SyntaxKind::LBrace) {
+                        break;
+                    }
+                    rest = tail;
+                }
+                Syntax::Block {
+                    header: header.join(" "),
+                    header_comments,
+                    body: items_from_cst(rest, tree),
+                    set_apart: node.kind != SyntaxKind::Expect,
+                }
+            }
+            SyntaxKind::FunctionCall | SyntaxKind::Command => {
+                comments.extend(
+                    node.descendant_tokens()
+                        .into_iter()
+                        .filter(|token| token.kind.is_comment())
+                        .map(comment_text),
+                );
+                let text = node
+                    .child(SyntaxKind::Name)
+                    .map(name_text)
+                    .unwrap_or_default();
+                match node.kind {
+                    SyntaxKind::FunctionCall => Syntax::Call(text),
+                    _ => Syntax::Command(text),
+                }
+            }
+            _ => continue,
+        };
+        items.push(Item {
+            syntax,
+            start,
+            end,
+            comments,
+        });
+    }
+    items
+}
+
+fn ir_text(text: &Option<TextWithArgs>) -> String {
+    let parts = text.iter().flat_map(|text| &text.parts);
+    let parts: Vec<String> = parts
+        .filter_map(|part| match &part.part_kind {
+            Some(PartKind::Text(text)) => Some(words(text)),
+            Some(PartKind::FunctionArg(arg)) => Some(format!("[{}]", words(&arg.text))),
+            None => None,
+        })
+        .collect();
+    parts.join(" ")
+}
+
+fn items_from_ir(lines: &[Line]) -> Vec<Item> {
+    lines
+        .iter()
+        .filter_map(|line| {
+            let syntax = match line.line_kind.as_ref()? {
+                LineKind::Function(function) => Syntax::Block {
+                    header: format!("> {}", ir_text(&function.name)),
+                    header_comments: Vec::new(),
+                    body: items_from_ir(&function.lines),
+                    set_apart: true,
+                },
+                LineKind::FunctionCall(call) => Syntax::Call(ir_text(&call.name)),
+                LineKind::Command(command) => Syntax::Command(ir_text(&command.text)),
+            };
+            Some(Item {
+                syntax,
+                start: 0,
+                end: 0,
+                comments: Vec::new(),
+            })
+        })
+        .collect()
+}
+
+/// Whether to leave a blank line before `items[i]`. The author's blank lines
+/// are kept (collapsed to one), and function definitions and tests, along
+/// with the comments directly above them, are always set apart from their
+/// neighbours.
+fn blank_before(items: &[Item], i: usize) -> bool {
+    let (previous, item) = (&items[i - 1], &items[i]);
+    if item.start > previous.end + 1 || previous.is_set_apart() {
+        return true;
+    }
+    if previous.is_comment() {
+        return false;
+    }
+    let mut next = i;
+    while items[next].is_comment()
+        && next + 1 < items.len()
+        && items[next + 1].start == items[next].end + 1
+    {
+        next += 1;
+    }
+    items[next].is_set_apart()
+}
+
+fn write_items(items: &[Item], depth: usize, result: &mut String) {
+    let indent = INDENT.repeat(depth);
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 && blank_before(items, i) {
+            result.push('\n');
+        }
+        result.push_str(&indent);
+        match &item.syntax {
+            Syntax::Block {
+                header,
+                header_comments,
+                body,
+                ..
+            } => {
+                result.push_str(&format!("{} {{", header));
+                for comment in header_comments {
+                    result.push(' ');
+                    result.push_str(comment);
+                }
+                result.push('\n');
+                write_items(body, depth + 1, result);
+                result.push_str(&indent);
+                result.push('}');
+            }
+            Syntax::Call(text) => result.push_str(&format!("\" {}", text)),
+            Syntax::Command(text) | Syntax::Comment(text) => result.push_str(text),
+        }
+        for comment in &item.comments {
+            result.push(' ');
+            result.push_str(comment);
+        }
+        result.push('\n');
+    }
+}
+
+/// Prints the program as canonical source: four spaces of indentation per
+/// block, single spaces between words and arguments, and at most one blank
+/// line in a row, with one around each function definition.
+pub(crate) fn write_program(program: &Program) -> String {
+    let mut result = String::new();
+    write_items(&items_from_ir(&program.lines), 0, &mut result);
+    result
+}
+
+/// Formats parsed source the same way as `write_program`, keeping its
+/// comments and blank lines.
+pub(crate) fn format_source(tree: &SyntaxTree) -> String {
+    let mut result = String::new();
+    write_items(&items_from_cst(&tree.root.children, tree), 0, &mut result);
+    result
+}
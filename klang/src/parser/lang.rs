@@ -1,129 +1,325 @@
+use super::ast::Program as AstProgram;
+use super::cst::{SyntaxElement, SyntaxKind, SyntaxNode, SyntaxTree};
 use super::errors::ParseError;
 use super::ir::{
-    line::LineKind, text_part::PartKind, Command, Function, FunctionArg, FunctionCall, Line,
-    Program, TextPart, TextWithArgs,
+    expect::ExpectKind, expect_state as state, line::LineKind, test_step::StepKind,
+    text_part::PartKind, Command, Expect, ExpectCommands, ExpectState, Function, FunctionArg,
+    FunctionCall, Line, Program, SourceLocation, Test, TestStep, TextPart, TextWithArgs,
 };
-use super::structs::Rule;
-use crate::parser::passes::ir_to_ast;
+use crate::parser::passes::lower::lower_commands;
+use crate::parser::passes::validate::validate_commands;
+use crate::parser::passes::{expand_lines, ir_to_ast, substitute_text_with_args};
+use crate::parser::schema::PrimitiveSchema;
+use crate::parser::structs::{Expectation, TestCase, TestStep as CompiledStep};
 use crate::parser::KlangProgram;
-use pest::iterators::Pair;
-
-pub fn parse_program(pair: pest::iterators::Pair<Rule>) -> Result<KlangProgram, ParseError> {
-    let mut all_lines = Vec::new();
-
-    for line_pair in pair.into_inner() {
-        match line_pair.as_rule() {
-            Rule::line => match parse_line(line_pair) {
-                Ok(lines) => all_lines.extend(lines),
-                Err(e) => return Err(e),
-            },
-            Rule::EOI => break,
-            _ => {
-                return Err(ParseError::from_pair(
-                    format!("Unknown rule: {:?}", line_pair.as_rule()),
-                    line_pair,
-                ));
-            }
-        }
-    }
+use std::collections::HashMap;
 
-    let ir_program = Program { lines: all_lines };
-    let ast_program = ir_to_ast(&ir_program)?;
+pub fn parse_program(
+    tree: &SyntaxTree,
+    schema: Option<&PrimitiveSchema>,
+) -> Result<KlangProgram, ParseError> {
+    let ir_program = parse_ir(tree)?;
+    let mut ast_program = ir_to_ast(&ir_program)?;
+    if let Some(schema) = schema {
+        validate_commands(&ast_program, schema)?;
+        lower_commands(&mut ast_program, schema);
+    }
 
     Ok(KlangProgram::from_ast(&ast_program))
 }
 
-fn parse_line(line: Pair<Rule>) -> Result<Vec<Line>, ParseError> {
-    line.into_inner()
-        .filter_map(|line_pair| match line_pair.as_rule() {
-            Rule::function_def => match parse_function_def(line_pair) {
-                Ok(func) => Some(Ok(Line {
-                    line_kind: Some(LineKind::Function(func)),
-                })),
-                Err(e) => Some(Err(e)),
-            },
-            Rule::function_call => match parse_function_call(line_pair) {
-                Ok(call) => Some(Ok(Line {
-                    line_kind: Some(LineKind::FunctionCall(call)),
-                })),
-                Err(e) => Some(Err(e)),
-            },
-            Rule::command => match parse_command(line_pair) {
-                Ok(cmd) => Some(Ok(Line {
-                    line_kind: Some(LineKind::Command(cmd)),
-                })),
-                Err(e) => Some(Err(e)),
-            },
-            Rule::empty_line => None,
-            _ => Some(Err(ParseError::from_pair(
-                format!("Unknown rule: {:?}", line_pair.as_rule()),
-                line_pair,
-            ))),
+/// Compiles the test blocks of the program, expanding their calls against
+/// the functions of the program.
+pub fn parse_tests(
+    tree: &SyntaxTree,
+    schema: Option<&PrimitiveSchema>,
+) -> Result<Vec<TestCase>, ParseError> {
+    let ir_program = parse_ir(tree)?;
+    ir_program
+        .tests
+        .iter()
+        .map(|test| {
+            let steps = test
+                .steps
+                .iter()
+                .filter_map(|step| step.step_kind.as_ref())
+                .map(|step| match step {
+                    StepKind::Line(line) => {
+                        let mut ast_program = AstProgram {
+                            commands: expand_lines(&ir_program, std::slice::from_ref(line))?,
+                            shared: Vec::new(),
+                        };
+                        if let Some(schema) = schema {
+                            validate_commands(&ast_program, schema)?;
+                            lower_commands(&mut ast_program, schema);
+                        }
+                        Ok(CompiledStep::Run(
+                            KlangProgram::from_ast(&ast_program).program,
+                        ))
+                    }
+                    StepKind::Expect(expect) => Ok(CompiledStep::Expect {
+                        expectation: expectation(expect)?,
+                        location: expect.location.map(|location| structs_location(&location)),
+                    }),
+                })
+                .collect::<Result<_, ParseError>>()?;
+            Ok(TestCase {
+                name: test.name.clone(),
+                location: test.location.map(|location| structs_location(&location)),
+                steps,
+            })
         })
         .collect()
 }
 
-fn parse_function_def(pair: Pair<Rule>) -> Result<Function, ParseError> {
-    let text_with_args = parse_text_with_args(pair.clone().into_inner().next().unwrap())?;
-    let children: Result<Vec<Line>, ParseError> = pair
-        .into_inner()
-        .skip(1)
-        .map(parse_line)
-        .try_fold(Vec::new(), |mut acc, result| {
-            acc.extend(result?);
-            Ok(acc)
-        });
-
-    Ok(Function {
-        name: Some(text_with_args),
-        lines: children?,
+fn structs_location(location: &SourceLocation) -> crate::parser::structs::SourceLocation {
+    crate::parser::structs::SourceLocation {
+        line: location.line as usize,
+        column: location.column as usize,
+    }
+}
+
+fn expectation(expect: &Expect) -> Result<Expectation, ParseError> {
+    let error = |message: &str| match expect.location {
+        Some(location) => ParseError::at(
+            message.to_string(),
+            location.line as usize,
+            location.column as usize,
+        ),
+        None => ParseError::new(message.to_string()),
+    };
+    let state = match &expect.expect_kind {
+        Some(ExpectKind::Commands(commands)) => {
+            return Ok(Expectation::Commands(commands.commands.clone()))
+        }
+        Some(ExpectKind::State(state)) => state,
+        None => return Err(error("Expected commands or a state to check")),
+    };
+    let number = state.value.parse::<f64>();
+    match (state.kind(), number) {
+        (state::Kind::Joint, _) if state.key.is_empty() => Err(error(
+            "Expected the joint to check, such as: expect joint \"joint=1 arm=left\" is 90",
+        )),
+        (state::Kind::Joint, Ok(position)) => Ok(Expectation::Joint {
+            key: state.key.clone(),
+            position,
+        }),
+        (state::Kind::Gripper, Err(_)) if matches!(state.value.as_str(), "open" | "closed") => {
+            let key = match state.key.as_str() {
+                "" => "gripper",
+                key => key,
+            };
+            Ok(Expectation::Gripper {
+                key: key.to_string(),
+                open: state.value == "open",
+            })
+        }
+        (state::Kind::Gripper, _) => Err(error("Expected a gripper to be open or closed")),
+        (state::Kind::Elapsed, _) if !state.key.is_empty() => {
+            Err(error("The elapsed time does not take a name"))
+        }
+        (state::Kind::Elapsed, Ok(seconds)) => Ok(Expectation::Elapsed(seconds)),
+        (_, _) => Err(error(&format!("Expected a number, got '{}'", state.value))),
+    }
+}
+
+/// Derives the IR from the syntax tree, dropping trivia.
+pub(super) fn parse_ir(tree: &SyntaxTree) -> Result<Program, ParseError> {
+    Ok(Program {
+        lines: parse_lines(tree, &tree.root)?,
+        tests: tree
+            .root
+            .nodes()
+            .filter(|node| node.kind == SyntaxKind::TestBlock)
+            .map(|node| parse_test(tree, node))
+            .collect::<Result<_, _>>()?,
     })
 }
 
-fn parse_function_call(pair: Pair<Rule>) -> Result<FunctionCall, ParseError> {
-    Ok(FunctionCall {
-        name: Some(parse_text_with_args(pair.into_inner().next().unwrap())?),
+fn source_location(tree: &SyntaxTree, node: &SyntaxNode) -> Option<SourceLocation> {
+    let (line, column) = tree.line_col(node.span.start);
+    Some(SourceLocation {
+        line: line as u32,
+        column: column as u32,
     })
 }
 
-fn parse_command(pair: Pair<Rule>) -> Result<Command, ParseError> {
-    Ok(Command {
-        text: Some(parse_text_with_args(pair.into_inner().next().unwrap())?),
+fn parse_lines(tree: &SyntaxTree, parent: &SyntaxNode) -> Result<Vec<Line>, ParseError> {
+    parent
+        .children
+        .iter()
+        .enumerate()
+        .filter_map(|(index, child)| match child {
+            SyntaxElement::Node(node)
+                if !matches!(node.kind, SyntaxKind::Name | SyntaxKind::TestBlock) =>
+            {
+                Some(parse_line(tree, parent, index, node))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn parse_line(
+    tree: &SyntaxTree,
+    parent: &SyntaxNode,
+    index: usize,
+    node: &SyntaxNode,
+) -> Result<Line, ParseError> {
+    let line_kind = match node.kind {
+        SyntaxKind::FunctionDef => LineKind::Function(Function {
+            name: Some(parse_name(tree, node)?),
+            lines: parse_lines(tree, node)?,
+            doc: doc_comment(parent, index),
+        }),
+        SyntaxKind::FunctionCall => LineKind::FunctionCall(FunctionCall {
+            name: Some(parse_name(tree, node)?),
+        }),
+        SyntaxKind::Command => LineKind::Command(Command {
+            text: Some(parse_name(tree, node)?),
+        }),
+        kind => return Err(error_at(tree, node, format!("Unexpected {:?}", kind))),
+    };
+    Ok(Line {
+        line_kind: Some(line_kind),
+        location: source_location(tree, node),
     })
 }
 
-fn parse_text_with_args(pair: Pair<Rule>) -> Result<TextWithArgs, ParseError> {
-    match pair.as_rule() {
-        Rule::text_with_function_args | Rule::text_with_function_params => {
-            let parts = pair
-                .into_inner()
-                .map(parse_text_part)
-                .collect::<Result<Vec<TextPart>, ParseError>>()?;
-            Ok(TextWithArgs { parts })
-        }
-        _ => Err(ParseError::from_pair(
-            format!("Expected text with args, got {:?}", pair.as_rule()),
-            pair,
-        )),
-    }
+fn error_at(tree: &SyntaxTree, node: &SyntaxNode, message: String) -> ParseError {
+    let (line, column) = tree.line_col(node.span.start);
+    ParseError::at(message, line, column)
 }
 
-fn parse_text_part(pair: Pair<Rule>) -> Result<TextPart, ParseError> {
-    match pair.as_rule() {
-        Rule::text => Ok(TextPart {
-            part_kind: Some(PartKind::Text(pair.as_str().to_string())),
+fn string_value(node: &SyntaxNode) -> Option<String> {
+    node.tokens()
+        .find(|token| token.kind == SyntaxKind::String)
+        .map(|token| token.text.trim_matches('"').to_string())
+}
+
+fn parse_test(tree: &SyntaxTree, node: &SyntaxNode) -> Result<Test, ParseError> {
+    let steps = node
+        .children
+        .iter()
+        .enumerate()
+        .filter_map(|(index, child)| match child {
+            SyntaxElement::Node(child) => Some((index, child)),
+            SyntaxElement::Token(_) => None,
+        })
+        .map(|(index, child)| {
+            let step_kind = match child.kind {
+                SyntaxKind::Expect => StepKind::Expect(parse_expect(tree, child)?),
+                _ => StepKind::Line(parse_line(tree, node, index, child)?),
+            };
+            Ok(TestStep {
+                step_kind: Some(step_kind),
+            })
+        })
+        .collect::<Result<_, ParseError>>()?;
+    Ok(Test {
+        name: string_value(node).unwrap_or_default(),
+        steps,
+        location: source_location(tree, node),
+    })
+}
+
+fn parse_expect(tree: &SyntaxTree, node: &SyntaxNode) -> Result<Expect, ParseError> {
+    let words: Vec<&str> = node
+        .tokens()
+        .filter(|token| matches!(token.kind, SyntaxKind::Keyword | SyntaxKind::Number))
+        .map(|token| token.text.as_str())
+        .collect();
+    let expect_kind = match words.get(1).copied() {
+        Some("commands") => ExpectKind::Commands(ExpectCommands {
+            commands: node
+                .nodes()
+                .map(|command| {
+                    let text = parse_name(tree, command)?;
+                    substitute_text_with_args(&text, &HashMap::new())
+                })
+                .collect::<Result<_, ParseError>>()?,
         }),
-        Rule::function_arg | Rule::function_param => {
-            let func_arg = FunctionArg {
-                text: pair.into_inner().next().unwrap().as_str().to_string(),
+        kind => {
+            let kind = match kind {
+                Some("joint") => state::Kind::Joint,
+                Some("gripper") => state::Kind::Gripper,
+                _ => state::Kind::Elapsed,
             };
-            Ok(TextPart {
-                part_kind: Some(PartKind::FunctionArg(func_arg)),
+            ExpectKind::State(ExpectState {
+                kind: kind as i32,
+                key: string_value(node).unwrap_or_default(),
+                value: words.last().copied().unwrap_or_default().to_string(),
             })
         }
-        _ => Err(ParseError::from_pair(
-            format!("Expected text or function arg, got {:?}", pair.as_rule()),
-            pair,
-        )),
+    };
+    Ok(Expect {
+        expect_kind: Some(expect_kind),
+        location: source_location(tree, node),
+    })
+}
+
+/// The `///` comments on the lines directly above `parent.children[index]`,
+/// without the slashes. A blank line or any other comment ends them.
+pub(super) fn doc_comment(parent: &SyntaxNode, index: usize) -> String {
+    let mut lines = Vec::new();
+    let mut newlines = 0;
+    for child in parent.children[..index].iter().rev() {
+        let SyntaxElement::Token(token) = child else {
+            break;
+        };
+        match token.kind {
+            SyntaxKind::Whitespace => {}
+            SyntaxKind::Newline if newlines == 0 => newlines += 1,
+            SyntaxKind::DocComment => {
+                let text = token.text.trim_end().trim_start_matches("///");
+                lines.push(text.strip_prefix(' ').unwrap_or(text));
+                newlines = 0;
+            }
+            _ => break,
+        }
     }
+    lines.reverse();
+    lines.join("\n")
+}
+
+fn parse_name(tree: &SyntaxTree, parent: &SyntaxNode) -> Result<TextWithArgs, ParseError> {
+    let Some(name) = parent.child(SyntaxKind::Name) else {
+        let (line, column) = tree.line_col(parent.span.start);
+        return Err(ParseError::at(
+            format!("Expected text in {:?}", parent.kind),
+            line,
+            column,
+        ));
+    };
+    Ok(text_with_args(name))
+}
+
+/// The words and arguments of a `Name` node.
+pub(super) fn text_with_args(name: &SyntaxNode) -> TextWithArgs {
+    // Spaces between arguments are not text of their own.
+    let parts = name
+        .children
+        .iter()
+        .filter_map(|child| match child {
+            SyntaxElement::Token(token) if token.kind == SyntaxKind::Text => {
+                let text = token.text.trim();
+                (!text.is_empty()).then(|| PartKind::Text(text.to_string()))
+            }
+            SyntaxElement::Node(node) if node.kind == SyntaxKind::Argument => {
+                let text = node
+                    .tokens()
+                    .filter(|token| token.kind == SyntaxKind::Text)
+                    .map(|token| token.text.as_str())
+                    .collect::<String>();
+                Some(PartKind::FunctionArg(FunctionArg {
+                    text: text.trim().to_string(),
+                }))
+            }
+            _ => None,
+        })
+        .map(|part_kind| TextPart {
+            part_kind: Some(part_kind),
+        })
+        .collect();
+    TextWithArgs { parts }
 }
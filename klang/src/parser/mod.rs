@@ -6,45 +6,167 @@ mod ir {
     include!(concat!(env!("OUT_DIR"), "/proto/ir.rs"));
 }
 
+pub mod analysis;
+pub mod cst;
+pub mod decompile;
+pub mod docs;
 pub mod errors;
+pub mod estimate;
+mod format;
 pub mod lang;
 pub mod passes;
+pub mod rename;
+pub mod robot;
+pub mod schema;
 pub mod structs;
+pub mod visualize;
 
+use cst::SyntaxTree;
 use errors::ParseError;
-use lang::parse_program;
-use pest::Parser;
+use lang::{parse_ir, parse_program, parse_tests};
+use passes::call_graph::CallGraph;
+use schema::PrimitiveSchema;
 use std::fs;
 use std::path::Path;
-use structs::{KlangProgram, PestParser, Rule};
+use structs::{KlangProgram, TestCase};
 
 pub fn parse_string(input: &str) -> Result<KlangProgram, ParseError> {
-    match PestParser::parse(Rule::program, input) {
-        Ok(mut pairs) => parse_program(pairs.next().unwrap()),
-        Err(e) => Err(ParseError::new(format!("Error parsing input: {}", e))),
-    }
+    parse_string_with_schema(input, None)
 }
 
 pub fn parse_file(file_path: &Path) -> Result<KlangProgram, ParseError> {
-    let unparsed_file = fs::read_to_string(file_path).map_err(|e| {
+    parse_string(&read_source(file_path)?)
+}
+
+/// Parses the input, and if a schema is given, rejects any command that is
+/// not one of its primitives and lowers the rest into actions.
+pub fn parse_string_with_schema(
+    input: &str,
+    schema: Option<&PrimitiveSchema>,
+) -> Result<KlangProgram, ParseError> {
+    parse_program(&SyntaxTree::parse(input)?, schema)
+}
+
+pub fn parse_file_with_schema(
+    file_path: &Path,
+    schema: Option<&PrimitiveSchema>,
+) -> Result<KlangProgram, ParseError> {
+    parse_string_with_schema(&read_source(file_path)?, schema)
+}
+
+/// Compiles the test blocks of the input, which compiling the program leaves
+/// out.
+pub fn tests_from_string(
+    input: &str,
+    schema: Option<&PrimitiveSchema>,
+) -> Result<Vec<TestCase>, ParseError> {
+    parse_tests(&SyntaxTree::parse(input)?, schema)
+}
+
+pub fn tests_from_file(
+    file_path: &Path,
+    schema: Option<&PrimitiveSchema>,
+) -> Result<Vec<TestCase>, ParseError> {
+    tests_from_string(&read_source(file_path)?, schema)
+}
+
+pub fn call_graph_from_string(input: &str) -> Result<CallGraph, ParseError> {
+    CallGraph::from_ir(&parse_ir(&SyntaxTree::parse(input)?)?)
+}
+
+pub fn call_graph_from_file(file_path: &Path) -> Result<CallGraph, ParseError> {
+    call_graph_from_string(&read_source(file_path)?)
+}
+
+/// Rewrites the source in canonical form, keeping its comments.
+pub fn format_string(input: &str) -> Result<String, ParseError> {
+    Ok(format::format_source(&SyntaxTree::parse(input)?))
+}
+
+pub fn format_file(file_path: &Path) -> Result<String, ParseError> {
+    format_string(&read_source(file_path)?)
+}
+
+/// The intermediate representation of the input, before functions are
+/// expanded, in a readable debug form.
+pub fn dump_ir_string(input: &str) -> Result<String, ParseError> {
+    Ok(format!("{:#?}", parse_ir(&SyntaxTree::parse(input)?)?))
+}
+
+pub fn dump_ir_file(file_path: &Path) -> Result<String, ParseError> {
+    dump_ir_string(&read_source(file_path)?)
+}
+
+fn read_source(file_path: &Path) -> Result<String, ParseError> {
+    fs::read_to_string(file_path).map_err(|e| {
         ParseError::new(format!(
             "Error reading file '{}': {}",
             file_path.display(),
             e
         ))
-    })?;
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Binary,
+    Text,
+    Json,
+    Yaml,
+}
 
-    parse_string(&unparsed_file)
+impl std::str::FromStr for OutputFormat {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "binary" => Ok(OutputFormat::Binary),
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "yaml" => Ok(OutputFormat::Yaml),
+            _ => Err(ParseError::new(format!(
+                "Unknown output format: {} (expected binary, text, json or yaml)",
+                s
+            ))),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// The extension of files written in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Binary => "ko",
+            OutputFormat::Text => "txt",
+            OutputFormat::Json => "json",
+            OutputFormat::Yaml => "yaml",
+        }
+    }
 }
 
 pub fn write_program_to_file(
     program: &KlangProgram,
     file_path: &Path,
-    binary: bool,
+    format: OutputFormat,
 ) -> Result<(), ParseError> {
-    if binary {
-        program.save_binary(file_path)
-    } else {
-        program.save_text(file_path)
+    match format {
+        OutputFormat::Binary => program.save_binary(file_path),
+        OutputFormat::Text => program.save_text(file_path),
+        OutputFormat::Json => program.save_json(file_path),
+        OutputFormat::Yaml => program.save_yaml(file_path),
+    }
+}
+
+pub fn read_program_from_file(
+    file_path: &Path,
+    format: OutputFormat,
+) -> Result<KlangProgram, ParseError> {
+    match format {
+        OutputFormat::Binary => KlangProgram::load_binary(file_path),
+        OutputFormat::Json => KlangProgram::load_json(file_path),
+        OutputFormat::Yaml => KlangProgram::load_yaml(file_path),
+        OutputFormat::Text => Err(ParseError::new(
+            "The text format cannot be read back into a program".to_string(),
+        )),
     }
 }
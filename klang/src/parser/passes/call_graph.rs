@@ -0,0 +1,181 @@
+use super::{collect_functions, get_function_signature, resolve_function, Functions};
+use crate::parser::errors::ParseError;
+use crate::parser::ir::{line::LineKind, Line, Program};
+use std::collections::HashMap;
+
+/// Name of the node representing the top level of the program.
+pub const PROGRAM_ROOT: &str = "<program>";
+
+pub struct CallEdge {
+    pub caller: String,
+    pub callee: String,
+    pub count: usize,
+    /// Distinct argument bindings used across the calls, such as
+    /// `joint=1, arm=[arm]`. Arguments forwarded from the caller's own
+    /// parameters are shown in brackets.
+    pub bindings: Vec<String>,
+}
+
+pub struct CallGraph {
+    pub functions: Vec<String>,
+    pub edges: Vec<CallEdge>,
+}
+
+impl CallGraph {
+    pub(crate) fn from_ir(ir_program: &Program) -> Result<Self, ParseError> {
+        let mut functions = Functions::new();
+        for line in &ir_program.lines {
+            collect_functions(line, &mut functions)?;
+        }
+        let mut signatures: Vec<String> = functions
+            .iter()
+            .map(|(signature, _)| signature.clone())
+            .collect();
+        signatures.sort();
+
+        let mut graph = CallGraph {
+            functions: signatures.clone(),
+            edges: Vec::new(),
+        };
+        graph.add_calls(
+            PROGRAM_ROOT,
+            &[],
+            &ir_program.lines,
+            &functions,
+            &signatures,
+        )?;
+        for signature in &signatures {
+            let Some((_, (func, params))) = functions.iter().find(|(s, _)| s == signature) else {
+                continue;
+            };
+            graph.add_calls(signature, params, &func.lines, &functions, &signatures)?;
+        }
+        Ok(graph)
+    }
+
+    fn add_calls(
+        &mut self,
+        caller: &str,
+        caller_params: &[String],
+        lines: &[Line],
+        functions: &Functions,
+        signatures: &[String],
+    ) -> Result<(), ParseError> {
+        let forwarded: HashMap<String, String> = caller_params
+            .iter()
+            .map(|param| (param.clone(), format!("[{}]", param)))
+            .collect();
+
+        for line in lines {
+            let Some(LineKind::FunctionCall(func_call)) = &line.line_kind else {
+                continue;
+            };
+            let Some(name) = &func_call.name else {
+                return Err(ParseError::new("Function call without name".to_string()));
+            };
+
+            let resolved = resolve_function(name, functions, &forwarded);
+            let Some(((callee, (_, params)), args)) = resolved else {
+                let (call_signature, _) = get_function_signature(name);
+                return Err(ParseError::new(format!(
+                    "Function not found: {{ {} }} Available functions: {{ {} }}",
+                    call_signature,
+                    signatures.join(", ")
+                )));
+            };
+
+            let binding = params
+                .iter()
+                .filter_map(|param| args.get(param).map(|value| format!("{}={}", param, value)))
+                .collect::<Vec<String>>()
+                .join(", ");
+            self.add_edge(caller, callee, binding);
+        }
+        Ok(())
+    }
+
+    fn add_edge(&mut self, caller: &str, callee: &str, binding: String) {
+        let edge = match self
+            .edges
+            .iter_mut()
+            .find(|edge| edge.caller == caller && edge.callee == callee)
+        {
+            Some(edge) => edge,
+            None => {
+                self.edges.push(CallEdge {
+                    caller: caller.to_string(),
+                    callee: callee.to_string(),
+                    count: 0,
+                    bindings: Vec::new(),
+                });
+                self.edges.last_mut().unwrap()
+            }
+        };
+        edge.count += 1;
+        if !binding.is_empty() && !edge.bindings.contains(&binding) {
+            edge.bindings.push(binding);
+        }
+    }
+
+    fn edge_label(edge: &CallEdge) -> Vec<String> {
+        let mut label = vec![if edge.count == 1 {
+            "1 call".to_string()
+        } else {
+            format!("{} calls", edge.count)
+        }];
+        label.extend(edge.bindings.iter().cloned());
+        label
+    }
+
+    pub fn to_dot(&self) -> String {
+        let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+
+        let mut result = String::from("digraph calls {\n    rankdir=LR;\n    node [shape=box];\n");
+        result.push_str(&format!("    {} [shape=ellipse];\n", quote(PROGRAM_ROOT)));
+        for function in &self.functions {
+            result.push_str(&format!("    {};\n", quote(function)));
+        }
+        for edge in &self.edges {
+            result.push_str(&format!(
+                "    {} -> {} [label={}];\n",
+                quote(&edge.caller),
+                quote(&edge.callee),
+                quote(&Self::edge_label(edge).join("\n")).replace('\n', "\\n"),
+            ));
+        }
+        result.push_str("}\n");
+        result
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        let escape = |text: &str| {
+            text.replace('"', "#quot;")
+                .replace('<', "#lt;")
+                .replace('>', "#gt;")
+        };
+        let mut ids = HashMap::new();
+        ids.insert(PROGRAM_ROOT, "f0".to_string());
+
+        let mut result = String::from("flowchart LR\n");
+        result.push_str(&format!("    f0([\"{}\"])\n", escape(PROGRAM_ROOT)));
+        for (i, function) in self.functions.iter().enumerate() {
+            let id = format!("f{}", i + 1);
+            result.push_str(&format!("    {}[\"{}\"]\n", id, escape(function)));
+            ids.insert(function.as_str(), id);
+        }
+        for edge in &self.edges {
+            let label = Self::edge_label(edge)
+                .iter()
+                .map(|line| escape(line))
+                .collect::<Vec<String>>()
+                .join("<br/>");
+            result.push_str(&format!(
+                "    {} -->|\"{}\"| {}\n",
+                ids[edge.caller.as_str()],
+                label,
+                ids[edge.callee.as_str()],
+            ));
+        }
+        result
+    }
+}
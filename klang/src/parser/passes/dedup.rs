@@ -0,0 +1,147 @@
+use crate::parser::ast::{Command as AstCommand, Program as AstProgram};
+use crate::parser::errors::ParseError;
+use prost::Message;
+use std::collections::HashMap;
+
+struct Subtree {
+    command: AstCommand,
+    children: Vec<usize>,
+    uses: usize,
+    shared_index: Option<u32>,
+}
+
+struct Interner {
+    subtrees: Vec<Subtree>,
+    ids: HashMap<(Vec<u8>, Vec<usize>), usize>,
+}
+
+impl Interner {
+    fn intern(&mut self, command: &AstCommand) -> usize {
+        let children: Vec<usize> = command
+            .children
+            .iter()
+            .map(|child| self.intern(child))
+            .collect();
+        let leaf = AstCommand {
+            children: Vec::new(),
+            ..command.clone()
+        };
+        let key = (leaf.encode_to_vec(), children.clone());
+        if let Some(&id) = self.ids.get(&key) {
+            return id;
+        }
+        let id = self.subtrees.len();
+        self.subtrees.push(Subtree {
+            command: leaf,
+            children,
+            uses: 0,
+            shared_index: None,
+        });
+        self.ids.insert(key, id);
+        id
+    }
+
+    fn count_uses(&mut self, id: usize) {
+        self.subtrees[id].uses += 1;
+        if self.subtrees[id].uses == 1 {
+            for child in self.subtrees[id].children.clone() {
+                self.count_uses(child);
+            }
+        }
+    }
+
+    fn build(&mut self, id: usize, shared: &mut Vec<AstCommand>) -> AstCommand {
+        if let Some(index) = self.subtrees[id].shared_index {
+            return shared_ref(index);
+        }
+
+        let children = self.subtrees[id]
+            .children
+            .clone()
+            .into_iter()
+            .map(|child| self.build(child, shared))
+            .collect();
+        let command = AstCommand {
+            children,
+            ..self.subtrees[id].command.clone()
+        };
+
+        // Only share subtrees whose inline encoding is larger than a reference.
+        let index = shared.len() as u32;
+        if self.subtrees[id].uses < 2 || command.encoded_len() <= shared_ref(index).encoded_len() {
+            return command;
+        }
+        shared.push(command);
+        self.subtrees[id].shared_index = Some(index);
+        shared_ref(index)
+    }
+}
+
+fn shared_ref(index: u32) -> AstCommand {
+    AstCommand {
+        shared_index: Some(index),
+        ..Default::default()
+    }
+}
+
+/// Replaces repeated command subtrees with references into a shared table.
+///
+/// Entries in the table only ever reference entries that come before them,
+/// so the result can be expanded in a single forward pass.
+pub(crate) fn deduplicate(program: &AstProgram) -> AstProgram {
+    let mut interner = Interner {
+        subtrees: Vec::new(),
+        ids: HashMap::new(),
+    };
+    let roots: Vec<usize> = program
+        .commands
+        .iter()
+        .map(|command| interner.intern(command))
+        .collect();
+    for &root in &roots {
+        interner.count_uses(root);
+    }
+
+    let mut shared = Vec::new();
+    let commands = roots
+        .into_iter()
+        .map(|root| interner.build(root, &mut shared))
+        .collect();
+    AstProgram { commands, shared }
+}
+
+fn expand_command(command: &AstCommand, table: &[AstCommand]) -> Result<AstCommand, ParseError> {
+    if let Some(index) = command.shared_index {
+        return table.get(index as usize).cloned().ok_or_else(|| {
+            ParseError::new(format!("Invalid shared command reference: {}", index))
+        });
+    }
+    Ok(AstCommand {
+        children: command
+            .children
+            .iter()
+            .map(|child| expand_command(child, table))
+            .collect::<Result<Vec<AstCommand>, ParseError>>()?,
+        ..command.clone()
+    })
+}
+
+/// Inlines every shared table reference, producing a program without a
+/// shared table.
+pub(crate) fn expand_shared(program: &AstProgram) -> Result<AstProgram, ParseError> {
+    let mut table: Vec<AstCommand> = Vec::with_capacity(program.shared.len());
+    for entry in &program.shared {
+        // Restricting lookups to earlier entries rules out reference cycles.
+        let expanded = expand_command(entry, &table)?;
+        table.push(expanded);
+    }
+
+    Ok(AstProgram {
+        commands: program
+            .commands
+            .iter()
+            .map(|command| expand_command(command, &table))
+            .collect::<Result<Vec<AstCommand>, ParseError>>()?,
+        shared: Vec::new(),
+    })
+}
@@ -0,0 +1,28 @@
+use crate::parser::ast::{Command as AstCommand, Program as AstProgram};
+use crate::parser::schema::PrimitiveSchema;
+use crate::parser::structs::Action;
+
+fn lower_command(command: &mut AstCommand, schema: &PrimitiveSchema) {
+    if !command.function.is_empty() {
+        for child in &mut command.children {
+            lower_command(child, schema);
+        }
+        return;
+    }
+
+    if let Ok((primitive, arguments)) = schema.match_command(&command.text) {
+        let action = Action {
+            name: primitive.name.clone(),
+            arguments,
+        };
+        command.action = Some(action.to_ast());
+    }
+}
+
+/// Attaches a structured action to every command that matches a primitive,
+/// so consumers don't have to parse the command text again.
+pub(crate) fn lower_commands(program: &mut AstProgram, schema: &PrimitiveSchema) {
+    for command in &mut program.commands {
+        lower_command(command, schema);
+    }
+}
@@ -1,11 +1,20 @@
-use super::ast::{Command as AstCommand, Program as AstProgram};
+use super::ast::{
+    BoundArgument, Command as AstCommand, Program as AstProgram,
+    SourceLocation as AstSourceLocation,
+};
 use super::errors::ParseError;
 use super::ir::{
-    line::LineKind, text_part::PartKind, Command, Function, Line, Program, TextWithArgs,
+    line::LineKind, text_part::PartKind, Command, Function, FunctionCall, Line, Program,
+    SourceLocation, TextWithArgs,
 };
 use std::collections::HashMap;
 
-fn get_function_signature(name: &TextWithArgs) -> (String, Vec<String>) {
+pub mod call_graph;
+pub(crate) mod dedup;
+pub(crate) mod lower;
+pub(crate) mod validate;
+
+pub(crate) fn get_function_signature(name: &TextWithArgs) -> (String, Vec<String>) {
     let mut signature = String::new();
     let mut params = Vec::new();
     let mut first = true;
@@ -31,6 +40,39 @@ fn get_function_signature(name: &TextWithArgs) -> (String, Vec<String>) {
     (signature, params)
 }
 
+/// A function with its signature and parameters.
+pub(crate) type DefinedFunction = (String, (Function, Vec<String>));
+
+/// The functions of a program in the order they are defined. A signature
+/// defined twice keeps its first definition.
+pub(crate) type Functions = Vec<DefinedFunction>;
+
+/// Resolves a call to the first definition, in source order, whose signature
+/// it matches, along with the arguments it binds. The compiler, the call
+/// graph and the editor tooling all resolve calls this way, so they agree
+/// on calls that more than one definition matches.
+pub(crate) fn resolve_call<'a, T>(
+    call: &TextWithArgs,
+    definitions: impl IntoIterator<Item = (&'a TextWithArgs, T)>,
+    scope: &HashMap<String, String>,
+) -> Option<(T, HashMap<String, String>)> {
+    definitions.into_iter().find_map(|(signature, definition)| {
+        Some((definition, match_function_call(call, signature, scope)?))
+    })
+}
+
+pub(super) fn resolve_function<'a>(
+    call: &TextWithArgs,
+    functions: &'a Functions,
+    scope: &HashMap<String, String>,
+) -> Option<(&'a DefinedFunction, HashMap<String, String>)> {
+    let definitions = functions.iter().filter_map(|entry| {
+        let (_, (function, _)) = entry;
+        Some((function.name.as_ref()?, entry))
+    });
+    resolve_call(call, definitions, scope)
+}
+
 fn match_function_call(
     call_name: &TextWithArgs,
     func_signature: &TextWithArgs,
@@ -76,7 +118,7 @@ fn match_function_call(
 
 fn process_line_with_args(
     line: &Line,
-    functions: &HashMap<String, (Function, Vec<String>)>,
+    functions: &Functions,
     call_stack: &mut Vec<String>,
     arg_map: &HashMap<String, String>,
 ) -> Result<Vec<AstCommand>, ParseError> {
@@ -86,67 +128,79 @@ fn process_line_with_args(
             LineKind::FunctionCall(func_call) => {
                 if let Some(name) = &func_call.name {
                     let (call_signature, _) = get_function_signature(name);
-                    for (func_sig, (func_def, _)) in functions {
-                        if let Some(name_def) = &func_def.name {
-                            if let Some(mut new_arg_map) =
-                                match_function_call(name, name_def, arg_map)
-                            {
-                                // Merge parent scope arguments with new arguments
-                                // New arguments take precedence over parent scope
-                                for (key, value) in arg_map.iter() {
-                                    new_arg_map
-                                        .entry(key.clone())
-                                        .or_insert_with(|| value.clone());
-                                }
-
-                                if call_stack.contains(func_sig) {
-                                    return Err(ParseError::new(format!(
-                                        "Recursive function call: {}",
-                                        func_sig
-                                    )));
-                                }
-                                call_stack.push(func_sig.clone());
-
-                                // Create parent command with function name
-                                let function_text = substitute_text_with_args(name, arg_map)?;
-
-                                // Process child commands
-                                let mut children = Vec::new();
-                                for inner_line in &func_def.lines {
-                                    let mut cmds = process_line_with_args(
-                                        inner_line,
-                                        functions,
-                                        call_stack,
-                                        &new_arg_map,
-                                    )?;
-                                    children.append(&mut cmds);
-                                }
-
-                                call_stack.pop();
-
-                                // Return single command with children
-                                return Ok(vec![AstCommand {
-                                    text: function_text,
-                                    children,
-                                }]);
-                            }
-                        }
+                    let Some(((func_sig, (func_def, params)), mut new_arg_map)) =
+                        resolve_function(name, functions, arg_map)
+                    else {
+                        return Err(ParseError::new(format!(
+                            "Function not found: {{ {} }} Available functions: {{ {} }}",
+                            call_signature,
+                            functions
+                                .iter()
+                                .map(|(signature, _)| signature.as_str())
+                                .collect::<Vec<&str>>()
+                                .join(", ")
+                        )));
+                    };
+                    // Merge parent scope arguments with new arguments
+                    // New arguments take precedence over parent scope
+                    for (key, value) in arg_map.iter() {
+                        new_arg_map
+                            .entry(key.clone())
+                            .or_insert_with(|| value.clone());
                     }
-                    Err(ParseError::new(format!(
-                        "Function not found: {{ {} }} Available functions: {{ {} }}",
-                        call_signature,
-                        functions
-                            .keys()
-                            .map(|k| k.as_str())
-                            .collect::<Vec<&str>>()
-                            .join(", ")
-                    )))
+
+                    if call_stack.contains(func_sig) {
+                        return Err(ParseError::new(format!(
+                            "Recursive function call: {}",
+                            func_sig
+                        )));
+                    }
+                    call_stack.push(func_sig.clone());
+
+                    // Create parent command with function name
+                    let function_text = substitute_text_with_args(name, arg_map)?;
+
+                    // Process child commands
+                    let mut children = Vec::new();
+                    for inner_line in &func_def.lines {
+                        let mut cmds = process_line_with_args(
+                            inner_line,
+                            functions,
+                            call_stack,
+                            &new_arg_map,
+                        )?;
+                        children.append(&mut cmds);
+                    }
+
+                    call_stack.pop();
+
+                    let arguments = params
+                        .iter()
+                        .filter_map(|param| {
+                            Some(BoundArgument {
+                                name: param.clone(),
+                                value: new_arg_map.get(param)?.clone(),
+                            })
+                        })
+                        .collect();
+
+                    // Return single command with children
+                    Ok(vec![AstCommand {
+                        text: function_text,
+                        children,
+                        shared_index: None,
+                        location: ast_location(&line.location),
+                        function: func_sig.clone(),
+                        action: None,
+                        arguments,
+                    }])
                 } else {
                     Err(ParseError::new("Function call without name".to_string()))
                 }
             }
             LineKind::Command(cmd) => {
-                let ast_command = process_command_with_args(cmd, arg_map)?;
+                let mut ast_command = process_command_with_args(cmd, arg_map)?;
+                ast_command.location = ast_location(&line.location);
                 Ok(vec![ast_command])
             }
         }
@@ -163,14 +217,21 @@ fn process_command_with_args(
         let text = substitute_text_with_args(text, arg_map)?;
         Ok(AstCommand {
             text,
-            children: Vec::new(),
+            ..Default::default()
         })
     } else {
         Err(ParseError::new("Command without text".to_string()))
     }
 }
 
-fn substitute_text_with_args(
+fn ast_location(location: &Option<SourceLocation>) -> Option<AstSourceLocation> {
+    location.as_ref().map(|location| AstSourceLocation {
+        line: location.line,
+        column: location.column,
+    })
+}
+
+pub(crate) fn substitute_text_with_args(
     text_with_args: &TextWithArgs,
     arg_map: &HashMap<String, String>,
 ) -> Result<String, ParseError> {
@@ -199,16 +260,15 @@ fn substitute_text_with_args(
     Ok(result)
 }
 
-fn collect_functions(
-    line: &Line,
-    functions: &mut HashMap<String, (Function, Vec<String>)>,
-) -> Result<(), ParseError> {
+pub(super) fn collect_functions(line: &Line, functions: &mut Functions) -> Result<(), ParseError> {
     if let Some(kind) = &line.line_kind {
         match kind {
             LineKind::Function(func) => {
                 if let Some(name) = &func.name {
                     let (signature, params) = get_function_signature(name);
-                    functions.insert(signature.clone(), (func.clone(), params));
+                    if !functions.iter().any(|(defined, _)| *defined == signature) {
+                        functions.push((signature, (func.clone(), params)));
+                    }
                     for inner_line in &func.lines {
                         collect_functions(inner_line, functions)?;
                     }
@@ -223,13 +283,14 @@ fn collect_functions(
 }
 
 pub(crate) fn ir_to_ast(ir_program: &Program) -> Result<AstProgram, ParseError> {
-    let mut functions = HashMap::new();
+    let mut functions = Functions::new();
     for line in &ir_program.lines {
         collect_functions(line, &mut functions)?;
     }
 
     let mut ast_program = AstProgram {
         commands: Vec::new(),
+        shared: Vec::new(),
     };
     let mut call_stack = Vec::new();
     for line in &ir_program.lines {
@@ -239,3 +300,45 @@ pub(crate) fn ir_to_ast(ir_program: &Program) -> Result<AstProgram, ParseError>
     }
     Ok(ast_program)
 }
+
+/// Expands a single call against the functions of the program, with `scope`
+/// giving the values of parameters visible at the call.
+pub(crate) fn expand_call(
+    ir_program: &Program,
+    name: &TextWithArgs,
+    scope: &HashMap<String, String>,
+) -> Result<Vec<AstCommand>, ParseError> {
+    let mut functions = Functions::new();
+    for line in &ir_program.lines {
+        collect_functions(line, &mut functions)?;
+    }
+    let call = Line {
+        line_kind: Some(LineKind::FunctionCall(FunctionCall {
+            name: Some(name.clone()),
+        })),
+        location: None,
+    };
+    process_line_with_args(&call, &functions, &mut Vec::new(), scope)
+}
+
+/// Expands lines that are not part of the program, such as the steps of a
+/// test, against the functions of the program.
+pub(crate) fn expand_lines(
+    ir_program: &Program,
+    lines: &[Line],
+) -> Result<Vec<AstCommand>, ParseError> {
+    let mut functions = Functions::new();
+    for line in &ir_program.lines {
+        collect_functions(line, &mut functions)?;
+    }
+    let mut commands = Vec::new();
+    for line in lines {
+        commands.append(&mut process_line_with_args(
+            line,
+            &functions,
+            &mut Vec::new(),
+            &HashMap::new(),
+        )?);
+    }
+    Ok(commands)
+}
@@ -0,0 +1,56 @@
+use crate::parser::ast::{Command as AstCommand, Program as AstProgram};
+use crate::parser::errors::{LocatedError, ParseError};
+use crate::parser::schema::{MatchError, PrimitiveSchema};
+use crate::parser::structs::SourceLocation;
+
+fn validate_command(
+    command: &AstCommand,
+    schema: &PrimitiveSchema,
+    errors: &mut Vec<LocatedError>,
+) {
+    if !command.function.is_empty() {
+        for child in &command.children {
+            validate_command(child, schema, errors);
+        }
+        return;
+    }
+
+    let message = match schema.match_command(&command.text) {
+        Ok(_) => return,
+        Err(MatchError::Unknown) => format!("Unknown command: {}", command.text),
+        Err(MatchError::InvalidArgument {
+            primitive,
+            slot,
+            value,
+            expected,
+        }) => format!(
+            "Invalid argument '{}' for '{}' of {} in command: {} (expected {})",
+            value, slot, primitive.name, command.text, expected
+        ),
+    };
+    errors.push(LocatedError {
+        message,
+        location: command.location.as_ref().map(|location| SourceLocation {
+            line: location.line as usize,
+            column: location.column as usize,
+        }),
+        path: None,
+    });
+}
+
+/// Checks every command in the program against the primitive schema,
+/// reporting all unknown commands and badly typed arguments at once.
+pub(crate) fn validate_commands(
+    program: &AstProgram,
+    schema: &PrimitiveSchema,
+) -> Result<(), ParseError> {
+    let mut errors = Vec::new();
+    for command in &program.commands {
+        validate_command(command, schema, &mut errors);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ParseError::from_errors(errors))
+    }
+}
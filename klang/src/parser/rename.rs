@@ -0,0 +1,404 @@
+use super::analysis::{trimmed, Analysis};
+use super::cst::{Span, SyntaxKind, SyntaxNode, SyntaxTree};
+use super::errors::ParseError;
+use super::ir::{text_part::PartKind, TextPart, TextWithArgs};
+use super::lang::text_with_args;
+use super::parse_string;
+use super::passes::get_function_signature;
+use std::collections::HashMap;
+
+/// A replacement of the source in `span`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edit {
+    pub span: Span,
+    pub text: String,
+}
+
+/// What renaming at a position would change.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RenameTarget {
+    Function(usize),
+    Parameter { definition: usize, name: String },
+}
+
+/// Applies edits that do not overlap to the source.
+pub fn apply_edits(source: &str, edits: &[Edit]) -> String {
+    let mut edits: Vec<&Edit> = edits.iter().collect();
+    edits.sort_by_key(|edit| edit.span.start);
+    let mut result = String::new();
+    let mut position = 0;
+    for edit in edits {
+        result.push_str(&source[position..edit.span.start]);
+        result.push_str(&edit.text);
+        position = edit.span.end;
+    }
+    result.push_str(&source[position..]);
+    result
+}
+
+/// Parses a signature such as `wave [arm] arm` the way the header of a
+/// definition is parsed.
+fn parse_signature(signature: &str) -> Result<TextWithArgs, ParseError> {
+    let invalid = || ParseError::new(format!("Invalid signature: {}", signature));
+    let source = format!("> {} {{\n}}\n", signature);
+    let tree = SyntaxTree::parse(&source).map_err(|_| invalid())?;
+    let name = tree
+        .root
+        .child(SyntaxKind::FunctionDef)
+        .and_then(|definition| definition.child(SyntaxKind::Name))
+        .ok_or_else(invalid)?;
+    let span = trimmed(&tree, name.span);
+    if &source[span.start..span.end] != signature.trim() {
+        return Err(invalid());
+    }
+    Ok(text_with_args(name))
+}
+
+fn part_text(part: &TextPart) -> String {
+    match &part.part_kind {
+        Some(PartKind::Text(text)) => text.clone(),
+        Some(PartKind::FunctionArg(arg)) => format!("[{}]", arg.text),
+        None => String::new(),
+    }
+}
+
+fn argument_text(argument: &SyntaxNode) -> String {
+    let text: String = argument
+        .tokens()
+        .filter(|token| token.kind == SyntaxKind::Text)
+        .map(|token| token.text.as_str())
+        .collect();
+    text.trim().to_string()
+}
+
+/// The span of the text inside the brackets of an argument.
+fn argument_span(tree: &SyntaxTree, argument: &SyntaxNode) -> Span {
+    let inner = Span {
+        start: argument.span.start + 1,
+        end: argument.span.end - 1,
+    };
+    trimmed(tree, inner)
+}
+
+impl Analysis {
+    fn syntax_tree(&self) -> Result<&SyntaxTree, ParseError> {
+        self.tree
+            .as_ref()
+            .ok_or_else(|| ParseError::new("Cannot rename in a file with errors".to_string()))
+    }
+
+    fn definition_index(&self, span: Span) -> Option<usize> {
+        self.definitions
+            .iter()
+            .position(|definition| definition.span == span)
+    }
+
+    /// The definition with the given signature.
+    pub fn find_definition(&self, signature: &str) -> Result<usize, ParseError> {
+        let (signature, _) = get_function_signature(&parse_signature(signature)?);
+        let mut matches =
+            (0..self.definitions.len()).filter(|i| self.definitions[*i].signature == signature);
+        match (matches.next(), matches.next()) {
+            (Some(definition), None) => Ok(definition),
+            (Some(_), Some(_)) => Err(ParseError::new(format!(
+                "Several functions are defined as: {}",
+                signature
+            ))),
+            (None, _) => Err(ParseError::new(format!(
+                "Function not found: {}",
+                signature
+            ))),
+        }
+    }
+
+    /// The function or parameter under the cursor, with the span of its
+    /// current name.
+    pub fn rename_target(&self, offset: usize) -> Option<(RenameTarget, Span)> {
+        let tree = self.tree.as_ref()?;
+        if let Some(target) = self.parameter_at(tree, &tree.root, offset, &[]) {
+            return Some(target);
+        }
+        if let Some(call) = self.call_at(offset) {
+            return Some((RenameTarget::Function(call.definition?), call.name_span));
+        }
+        let definition = self.definition_at(offset)?;
+        Some((
+            RenameTarget::Function(definition),
+            self.definitions[definition].name_span,
+        ))
+    }
+
+    fn parameter_at(
+        &self,
+        tree: &SyntaxTree,
+        node: &SyntaxNode,
+        offset: usize,
+        scope: &[usize],
+    ) -> Option<(RenameTarget, Span)> {
+        for child in node.nodes().filter(|child| child.span.contains(offset)) {
+            match child.kind {
+                SyntaxKind::FunctionDef => {
+                    let mut scope = scope.to_vec();
+                    scope.push(self.definition_index(child.span)?);
+                    return self.parameter_at(tree, child, offset, &scope);
+                }
+                SyntaxKind::FunctionCall | SyntaxKind::Command => {
+                    return self.parameter_at(tree, child, offset, scope);
+                }
+                SyntaxKind::Name => {
+                    let argument = child
+                        .nodes()
+                        .find(|argument| argument.span.contains(offset))?;
+                    let name = argument_text(argument);
+                    // A signature declares its parameters; anywhere else the
+                    // innermost function with that parameter binds it.
+                    let definition = if node.kind == SyntaxKind::FunctionDef {
+                        *scope.last()?
+                    } else {
+                        *scope
+                            .iter()
+                            .rev()
+                            .find(|i| self.definitions[**i].params.contains(&name))?
+                    };
+                    let target = RenameTarget::Parameter { definition, name };
+                    return Some((target, argument_span(tree, argument)));
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// The edits that rename whatever is under the cursor. Functions take a
+    /// whole new signature, and parameters a new name.
+    pub fn rename_at(&self, offset: usize, new_name: &str) -> Result<Vec<Edit>, ParseError> {
+        match self.rename_target(offset) {
+            Some((RenameTarget::Function(definition), _)) => {
+                self.rename_function(definition, new_name)
+            }
+            Some((RenameTarget::Parameter { definition, name }, _)) => {
+                self.rename_parameter(definition, &name, new_name)
+            }
+            None => Err(ParseError::new(
+                "There is no function or parameter to rename here".to_string(),
+            )),
+        }
+    }
+
+    /// Changes the signature of a definition and rewrites every call to it.
+    /// The new signature must have the same parameters, in any order.
+    pub fn rename_function(
+        &self,
+        definition: usize,
+        signature: &str,
+    ) -> Result<Vec<Edit>, ParseError> {
+        let old = &self.definitions[definition];
+        let new = parse_signature(signature)?;
+        let (new_signature, new_params) = get_function_signature(&new);
+        let (mut old_sorted, mut new_sorted) = (old.params.clone(), new_params);
+        old_sorted.sort();
+        new_sorted.sort();
+        if old_sorted != new_sorted {
+            return Err(ParseError::new(format!(
+                "The new signature must have the same parameters as: {}",
+                old.signature
+            )));
+        }
+
+        let exists = self
+            .definitions
+            .iter()
+            .enumerate()
+            .any(|(i, other)| i != definition && other.signature == new_signature);
+        if exists {
+            return Err(ParseError::new(format!(
+                "A function is already defined as: {}",
+                new_signature
+            )));
+        }
+
+        let mut edits = vec![Edit {
+            span: old.name_span,
+            text: new_signature,
+        }];
+        for call in self.references(definition) {
+            // Keep each argument as written, whether or not it is bracketed.
+            let arguments: HashMap<&str, String> = call
+                .name
+                .parts
+                .iter()
+                .zip(&old.name.parts)
+                .filter_map(|(argument, part)| match &part.part_kind {
+                    Some(PartKind::FunctionArg(param)) => {
+                        Some((param.text.as_str(), part_text(argument)))
+                    }
+                    _ => None,
+                })
+                .collect();
+            let parts: Vec<String> = new
+                .parts
+                .iter()
+                .map(|part| match &part.part_kind {
+                    Some(PartKind::FunctionArg(param)) => arguments[param.text.as_str()].clone(),
+                    _ => part_text(part),
+                })
+                .collect();
+            edits.push(Edit {
+                span: call.name_span,
+                text: parts.join(" "),
+            });
+        }
+        self.check_rename(edits)
+    }
+
+    /// Renames a parameter in the signature of a definition and everywhere
+    /// it is used in the body, except inside nested functions that declare a
+    /// parameter with the same name.
+    pub fn rename_parameter(
+        &self,
+        definition: usize,
+        old: &str,
+        new: &str,
+    ) -> Result<Vec<Edit>, ParseError> {
+        let tree = self.syntax_tree()?;
+        let new = new
+            .trim()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .trim();
+        let function = &self.definitions[definition];
+        if !function.params.iter().any(|param| param == old) {
+            return Err(ParseError::new(format!(
+                "'{}' is not a parameter of: {}",
+                old, function.signature
+            )));
+        }
+        let shadowing = self.definitions.iter().enumerate().find(|(i, inner)| {
+            inner.params.iter().any(|param| param == new) && self.is_within(*i, definition)
+        });
+        if let Some((_, inner)) = shadowing {
+            return Err(ParseError::new(format!(
+                "'{}' is already a parameter of: {}",
+                new, inner.signature
+            )));
+        }
+
+        let node = find_node(&tree.root, function.span).ok_or_else(|| {
+            ParseError::new(format!("Function not found: {}", function.signature))
+        })?;
+        // Uses of `[new]` in the body would otherwise stop referring to an
+        // enclosing function's parameter.
+        let mut uses = Vec::new();
+        self.rename_arguments(tree, node, new, new, &mut uses);
+        let mut ancestor = function.parent;
+        while let Some(i) = ancestor {
+            let outer = &self.definitions[i];
+            if !uses.is_empty() && outer.params.iter().any(|param| param == new) {
+                return Err(ParseError::new(format!(
+                    "'{}' is already used in the body to refer to a parameter of: {}",
+                    new, outer.signature
+                )));
+            }
+            ancestor = outer.parent;
+        }
+
+        let mut edits = Vec::new();
+        self.rename_arguments(tree, node, old, new, &mut edits);
+        self.check_rename(edits)
+    }
+
+    /// Whether the definition is `ancestor` or nested inside it.
+    fn is_within(&self, definition: usize, ancestor: usize) -> bool {
+        let mut current = Some(definition);
+        while let Some(i) = current {
+            if i == ancestor {
+                return true;
+            }
+            current = self.definitions[i].parent;
+        }
+        false
+    }
+
+    fn rename_arguments(
+        &self,
+        tree: &SyntaxTree,
+        node: &SyntaxNode,
+        old: &str,
+        new: &str,
+        edits: &mut Vec<Edit>,
+    ) {
+        for child in node.nodes() {
+            match child.kind {
+                SyntaxKind::Name => {
+                    for argument in child.nodes() {
+                        if argument_text(argument) == old {
+                            edits.push(Edit {
+                                span: argument_span(tree, argument),
+                                text: new.to_string(),
+                            });
+                        }
+                    }
+                }
+                SyntaxKind::FunctionDef => {
+                    let declares = self
+                        .definition_index(child.span)
+                        .is_some_and(|i| self.definitions[i].params.iter().any(|p| p == old));
+                    if !declares {
+                        self.rename_arguments(tree, child, old, new, edits);
+                    }
+                }
+                _ => self.rename_arguments(tree, child, old, new, edits),
+            }
+        }
+    }
+
+    /// Rejects edits that leave the source unparseable, change which
+    /// function any call resolves to or change the commands it compiles to.
+    fn check_rename(&self, edits: Vec<Edit>) -> Result<Vec<Edit>, ParseError> {
+        let tree = self.syntax_tree()?;
+        let renamed_source = apply_edits(&tree.source, &edits);
+        let renamed = Analysis::new(&renamed_source);
+        if renamed.tree.is_none() {
+            let message = renamed
+                .diagnostics
+                .first()
+                .map_or_else(String::new, |diagnostic| diagnostic.message.clone());
+            return Err(ParseError::new(format!("Invalid name: {}", message)));
+        }
+        let before = self.calls.iter().map(|call| call.definition);
+        let after = renamed.calls.iter().map(|call| call.definition);
+        if !before.eq(after) {
+            return Err(ParseError::new(
+                "The rename would change which function a call resolves to".to_string(),
+            ));
+        }
+        if commands(&tree.source) != commands(&renamed_source) {
+            return Err(ParseError::new(
+                "The rename would change the commands the program runs".to_string(),
+            ));
+        }
+        Ok(edits)
+    }
+}
+
+/// The commands the source compiles to, in order, or `None` if it does not
+/// compile.
+fn commands(source: &str) -> Option<Vec<String>> {
+    let program = parse_string(source).ok()?;
+    Some(
+        program
+            .to_list()
+            .into_iter()
+            .filter_map(|line| line.into_iter().next())
+            .collect(),
+    )
+}
+
+fn find_node(node: &SyntaxNode, span: Span) -> Option<&SyntaxNode> {
+    if node.span == span && node.kind == SyntaxKind::FunctionDef {
+        return Some(node);
+    }
+    node.nodes()
+        .filter(|child| child.span.start <= span.start && span.end <= child.span.end)
+        .find_map(|child| find_node(child, span))
+}
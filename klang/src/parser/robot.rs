@@ -0,0 +1,204 @@
+use super::errors::{LocatedError, ParseError};
+use super::schema::{PrimitiveSchema, Value};
+use super::structs::{Action, KlangProgram, Node};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Action arguments that hold the position a joint should move to.
+pub(crate) const TARGET_ARGUMENTS: [&str; 3] = ["angle", "position", "target"];
+
+/// Action arguments that hold the speed of a joint move.
+const VELOCITY_ARGUMENTS: [&str; 2] = ["velocity", "speed"];
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct JointLimits {
+    pub name: String,
+    /// Action arguments identifying the joint, such as `joint = 1` and
+    /// `arm = "left"`. A joint with no arguments matches every joint move.
+    #[serde(default, rename = "match", deserialize_with = "match_values")]
+    pub arguments: BTreeMap<String, Value>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub max_velocity: Option<f64>,
+}
+
+fn match_values<'de, D>(deserializer: D) -> Result<BTreeMap<String, Value>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let values = BTreeMap::<String, toml::Value>::deserialize(deserializer)?;
+    values
+        .into_iter()
+        .map(|(name, value)| match value {
+            toml::Value::Integer(value) => Ok((name, Value::Int(value))),
+            toml::Value::Float(value) => Ok((name, Value::Number(value))),
+            toml::Value::String(value) => Ok((name, Value::Text(value))),
+            value => Err(serde::de::Error::custom(format!(
+                "match argument '{}' must be a number or a string, not {}",
+                name, value
+            ))),
+        })
+        .collect()
+}
+
+impl JointLimits {
+    fn matches(&self, action: &Action) -> bool {
+        self.arguments.iter().all(|(name, expected)| {
+            action.argument(name).is_some_and(|value| {
+                match (value.as_number(), expected.as_number()) {
+                    (Some(value), Some(expected)) => value == expected,
+                    (None, None) => value == expected,
+                    _ => false,
+                }
+            })
+        })
+    }
+
+    fn check(&self, action: &Action, target: f64, violations: &mut Vec<String>) {
+        let below = self.min.is_some_and(|min| target < min);
+        let above = self.max.is_some_and(|max| target > max);
+        // NaN compares false with both bounds, so it has to be caught here.
+        if !target.is_finite() || below || above {
+            let bound = |bound: Option<f64>| bound.map(|b| b.to_string()).unwrap_or_default();
+            violations.push(format!(
+                "Joint '{}' target {} is outside its range [{}, {}]",
+                self.name,
+                target,
+                bound(self.min),
+                bound(self.max)
+            ));
+        }
+        let velocity = VELOCITY_ARGUMENTS
+            .iter()
+            .find_map(|name| action.argument(name)?.as_number());
+        if let (Some(velocity), Some(max_velocity)) = (velocity, self.max_velocity) {
+            if velocity.abs() > max_velocity {
+                violations.push(format!(
+                    "Joint '{}' velocity {} exceeds its maximum of {}",
+                    self.name, velocity, max_velocity
+                ));
+            }
+        }
+    }
+}
+
+/// The joints of a robot and their safe operating envelope, written in TOML:
+///
+/// ```toml
+/// [[joints]]
+/// name = "left_shoulder"
+/// match = { joint = 1, arm = "left" }
+/// min = -90.0
+/// max = 90.0
+/// max_velocity = 45.0
+/// ```
+///
+/// A joint move is an action with a numeric `angle`, `position` or `target`
+/// argument. Its target is checked against every joint whose `match`
+/// arguments it has, and so is its `velocity` or `speed` argument if given.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RobotDescription {
+    #[serde(default)]
+    pub joints: Vec<JointLimits>,
+}
+
+impl RobotDescription {
+    pub fn from_toml(input: &str) -> Result<Self, ParseError> {
+        toml::from_str(input)
+            .map_err(|e| ParseError::new(format!("Error parsing robot description: {}", e)))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ParseError> {
+        let input = fs::read_to_string(path).map_err(|e| {
+            ParseError::new(format!(
+                "Error reading robot description '{}': {}",
+                path.display(),
+                e
+            ))
+        })?;
+        RobotDescription::from_toml(&input)
+    }
+
+    /// Rejects joints that match on an argument no primitive has, since
+    /// they would never apply to any command.
+    pub fn check_schema(&self, schema: &PrimitiveSchema) -> Result<(), ParseError> {
+        let errors: Vec<String> = self
+            .joints
+            .iter()
+            .flat_map(|joint| {
+                joint
+                    .arguments
+                    .keys()
+                    .filter(|name| {
+                        !schema
+                            .primitives
+                            .iter()
+                            .any(|primitive| primitive.has_slot(name))
+                    })
+                    .map(move |name| {
+                        format!(
+                            "Joint '{}' matches on '{}', which is not an argument of any primitive",
+                            joint.name, name
+                        )
+                    })
+            })
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ParseError::new(errors.join("\n")))
+        }
+    }
+
+    /// Every way in which the command leaves the safe envelope. Commands
+    /// that were not lowered into actions cannot be checked.
+    pub fn check_command(&self, node: &Node) -> Vec<String> {
+        let mut violations = Vec::new();
+        let Some(action) = &node.action else {
+            return violations;
+        };
+        let Some(target) = TARGET_ARGUMENTS
+            .iter()
+            .find_map(|name| action.argument(name)?.as_number())
+        else {
+            return violations;
+        };
+        for joint in self.joints.iter().filter(|joint| joint.matches(action)) {
+            joint.check(action, target, &mut violations);
+        }
+        violations
+    }
+
+    fn check_nodes(&self, nodes: &[Node], errors: &mut Vec<LocatedError>) {
+        for node in nodes {
+            if node.is_function() {
+                self.check_nodes(&node.children, errors);
+                continue;
+            }
+            for violation in self.check_command(node) {
+                let message = format!("{} in command: {}", violation, node.text);
+                errors.push(LocatedError {
+                    message,
+                    location: node.location,
+                    path: None,
+                });
+            }
+        }
+    }
+}
+
+impl KlangProgram {
+    /// Checks every joint move against the robot's limits, reporting all
+    /// violations at once.
+    pub fn check_safety(&self, robot: &RobotDescription) -> Result<(), ParseError> {
+        let mut errors = Vec::new();
+        robot.check_nodes(&self.program, &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ParseError::from_errors(errors))
+        }
+    }
+}
@@ -0,0 +1,409 @@
+use super::errors::ParseError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Value {
+    Int(i64),
+    Number(f64),
+    Text(String),
+}
+
+impl Value {
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Int(value) => Some(*value as f64),
+            Value::Number(value) => Some(*value),
+            Value::Text(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Number(value) => write!(f, "{}", value),
+            Value::Text(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Typed arguments bound to the slots of a primitive, in pattern order.
+pub type Arguments = Vec<(String, Value)>;
+
+#[derive(Clone, Debug)]
+pub enum SlotType {
+    /// A whole number, such as a joint index.
+    Int,
+    /// Any number, such as an angle.
+    Number,
+    /// A single word.
+    Word,
+    /// One or more words.
+    Text,
+    /// One of a fixed set of words, declared in the `[types]` table.
+    Enum(String, Vec<String>),
+}
+
+impl SlotType {
+    fn parse(&self, text: &str) -> Option<Value> {
+        match self {
+            SlotType::Int => text.parse().ok().map(Value::Int),
+            SlotType::Number => text
+                .parse::<f64>()
+                .ok()
+                .filter(|number| number.is_finite())
+                .map(Value::Number),
+            SlotType::Word | SlotType::Text => Some(Value::Text(text.to_string())),
+            SlotType::Enum(_, variants) => variants
+                .iter()
+                .any(|variant| variant == text)
+                .then(|| Value::Text(text.to_string())),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            SlotType::Int => "int".to_string(),
+            SlotType::Number => "number".to_string(),
+            SlotType::Word => "word".to_string(),
+            SlotType::Text => "text".to_string(),
+            SlotType::Enum(name, variants) => format!("{} ({})", name, variants.join(", ")),
+        }
+    }
+}
+
+/// How long a command takes, in seconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DurationEstimate {
+    pub expected: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl DurationEstimate {
+    pub fn exact(seconds: f64) -> Self {
+        DurationEstimate {
+            expected: seconds,
+            min: seconds,
+            max: seconds,
+        }
+    }
+}
+
+impl std::ops::Add for DurationEstimate {
+    type Output = DurationEstimate;
+
+    fn add(self, other: DurationEstimate) -> DurationEstimate {
+        DurationEstimate {
+            expected: self.expected + other.expected,
+            min: self.min + other.min,
+            max: self.max + other.max,
+        }
+    }
+}
+
+impl std::fmt::Display for DurationEstimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.1}s (min {:.1}s, max {:.1}s)",
+            self.expected, self.min, self.max
+        )
+    }
+}
+
+/// The duration of a primitive, written as a number of seconds, a table
+/// with `expected` and optional `min` and `max`, or the name of the slot
+/// holding the duration (such as `"seconds"` for a wait).
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum DurationSpec {
+    Fixed(f64),
+    Range {
+        expected: f64,
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+    Slot(String),
+}
+
+impl DurationSpec {
+    /// The estimate for a command with the given arguments, or None if the
+    /// duration slot is missing or not finite. Negative durations count as
+    /// zero.
+    pub fn estimate(&self, arguments: &Arguments) -> Option<DurationEstimate> {
+        match self {
+            DurationSpec::Fixed(seconds) => Some(DurationEstimate::exact(*seconds)),
+            DurationSpec::Range { expected, min, max } => Some(DurationEstimate {
+                expected: *expected,
+                min: min.unwrap_or(*expected),
+                max: max.unwrap_or(*expected),
+            }),
+            DurationSpec::Slot(slot) => arguments
+                .iter()
+                .find(|(name, _)| name == slot)
+                .and_then(|(_, value)| value.as_number())
+                .filter(|seconds| seconds.is_finite())
+                .map(|seconds| DurationEstimate::exact(seconds.max(0.0))),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum PatternToken {
+    Word(String),
+    Slot(String, SlotType),
+}
+
+#[derive(Clone, Debug)]
+pub struct Primitive {
+    pub name: String,
+    pub pattern: String,
+    pub duration: Option<DurationSpec>,
+    tokens: Vec<PatternToken>,
+}
+
+/// Why a command did not match any primitive.
+pub enum MatchError<'a> {
+    Unknown,
+    InvalidArgument {
+        primitive: &'a Primitive,
+        slot: String,
+        value: String,
+        expected: String,
+    },
+}
+
+impl Primitive {
+    /// Matches the command words against the pattern, binding slot values.
+    /// When `typed` is false, slot values are bound without checking their
+    /// types.
+    fn match_words(
+        tokens: &[PatternToken],
+        words: &[&str],
+        typed: bool,
+        bound: &mut Vec<(String, String)>,
+    ) -> bool {
+        match tokens.split_first() {
+            None => words.is_empty(),
+            Some((PatternToken::Word(word), rest)) => {
+                words.first() == Some(&word.as_str())
+                    && Primitive::match_words(rest, &words[1..], typed, bound)
+            }
+            Some((PatternToken::Slot(name, slot_type), rest)) => {
+                let max_len = match slot_type {
+                    SlotType::Text => words.len(),
+                    _ => words.len().min(1),
+                };
+                for len in 1..=max_len {
+                    let value = words[..len].join(" ");
+                    if typed && slot_type.parse(&value).is_none() {
+                        continue;
+                    }
+                    bound.push((name.clone(), value));
+                    if Primitive::match_words(rest, &words[len..], typed, bound) {
+                        return true;
+                    }
+                    bound.pop();
+                }
+                false
+            }
+        }
+    }
+
+    pub fn has_slot(&self, slot: &str) -> bool {
+        self.slot_type(slot).is_some()
+    }
+
+    fn slot_type(&self, slot: &str) -> Option<&SlotType> {
+        self.tokens.iter().find_map(|token| match token {
+            PatternToken::Slot(name, slot_type) if name == slot => Some(slot_type),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct SchemaFile {
+    #[serde(default)]
+    types: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    primitives: Vec<PrimitiveFile>,
+}
+
+#[derive(Deserialize)]
+struct PrimitiveFile {
+    name: String,
+    pattern: String,
+    duration: Option<DurationSpec>,
+}
+
+/// The vocabulary of commands a robot understands.
+///
+/// Schemas are written in TOML (or YAML, for `.yaml` and `.yml` files):
+///
+/// ```toml
+/// [types]
+/// side = ["left", "right"]
+///
+/// [[primitives]]
+/// name = "move_joint"
+/// pattern = "move joint {joint: int} on the {arm: side} arm to {angle: number}"
+/// ```
+///
+/// Slots are written as `{name: type}`, where the type is `int`, `number`,
+/// `word`, `text` or one of the names in `[types]`. A slot without a type
+/// matches a single word. A primitive may also give its `duration`, which is
+/// used to estimate how long programs take.
+#[derive(Clone, Debug, Default)]
+pub struct PrimitiveSchema {
+    pub primitives: Vec<Primitive>,
+}
+
+impl PrimitiveSchema {
+    pub fn from_toml(input: &str) -> Result<Self, ParseError> {
+        let file: SchemaFile = toml::from_str(input)
+            .map_err(|e| ParseError::new(format!("Error parsing primitive schema: {}", e)))?;
+        PrimitiveSchema::from_schema_file(file)
+    }
+
+    pub fn from_yaml(input: &str) -> Result<Self, ParseError> {
+        let file: SchemaFile = serde_yaml::from_str(input)
+            .map_err(|e| ParseError::new(format!("Error parsing primitive schema: {}", e)))?;
+        PrimitiveSchema::from_schema_file(file)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ParseError> {
+        let input = fs::read_to_string(path).map_err(|e| {
+            ParseError::new(format!(
+                "Error reading primitive schema '{}': {}",
+                path.display(),
+                e
+            ))
+        })?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml") | Some("yml") => PrimitiveSchema::from_yaml(&input),
+            _ => PrimitiveSchema::from_toml(&input),
+        }
+    }
+
+    fn from_schema_file(file: SchemaFile) -> Result<Self, ParseError> {
+        let primitives = file
+            .primitives
+            .into_iter()
+            .map(|primitive| {
+                let tokens = parse_pattern(&primitive.pattern, &file.types).map_err(|e| {
+                    ParseError::new(format!("Invalid pattern for '{}': {}", primitive.name, e))
+                })?;
+                if let Some(DurationSpec::Slot(slot)) = &primitive.duration {
+                    let numeric = tokens.iter().any(|token| {
+                        matches!(token, PatternToken::Slot(name, SlotType::Int | SlotType::Number) if name == slot)
+                    });
+                    if !numeric {
+                        return Err(ParseError::new(format!(
+                            "Invalid duration for '{}': '{}' is not a number slot",
+                            primitive.name, slot
+                        )));
+                    }
+                }
+                Ok(Primitive {
+                    name: primitive.name,
+                    pattern: primitive.pattern,
+                    duration: primitive.duration,
+                    tokens,
+                })
+            })
+            .collect::<Result<Vec<Primitive>, ParseError>>()?;
+        Ok(PrimitiveSchema { primitives })
+    }
+
+    /// Finds the first primitive matching the command text and returns its
+    /// typed arguments.
+    pub fn match_command(&self, text: &str) -> Result<(&Primitive, Arguments), MatchError<'_>> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        for primitive in &self.primitives {
+            let mut bound = Vec::new();
+            if Primitive::match_words(&primitive.tokens, &words, true, &mut bound) {
+                let args = bound
+                    .into_iter()
+                    .map(|(slot, value)| {
+                        let value = primitive.slot_type(&slot).unwrap().parse(&value).unwrap();
+                        (slot, value)
+                    })
+                    .collect();
+                return Ok((primitive, args));
+            }
+        }
+
+        for primitive in &self.primitives {
+            let mut bound = Vec::new();
+            if Primitive::match_words(&primitive.tokens, &words, false, &mut bound) {
+                for (slot, value) in bound {
+                    let slot_type = primitive.slot_type(&slot).unwrap();
+                    if slot_type.parse(&value).is_none() {
+                        return Err(MatchError::InvalidArgument {
+                            primitive,
+                            slot,
+                            value,
+                            expected: slot_type.name(),
+                        });
+                    }
+                }
+            }
+        }
+        Err(MatchError::Unknown)
+    }
+}
+
+fn parse_slot_type(name: &str, types: &HashMap<String, Vec<String>>) -> Result<SlotType, String> {
+    match name {
+        "int" => Ok(SlotType::Int),
+        "number" => Ok(SlotType::Number),
+        "word" | "" => Ok(SlotType::Word),
+        "text" => Ok(SlotType::Text),
+        _ => match types.get(name) {
+            Some(variants) => Ok(SlotType::Enum(name.to_string(), variants.clone())),
+            None => Err(format!("unknown slot type '{}'", name)),
+        },
+    }
+}
+
+fn parse_pattern(
+    pattern: &str,
+    types: &HashMap<String, Vec<String>>,
+) -> Result<Vec<PatternToken>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        tokens.extend(
+            rest[..start]
+                .split_whitespace()
+                .map(|word| PatternToken::Word(word.to_string())),
+        );
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| "unterminated slot".to_string())?
+            + start;
+        let slot = &rest[start + 1..end];
+        let (name, slot_type) = slot.split_once(':').unwrap_or((slot, ""));
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("slot without a name".to_string());
+        }
+        tokens.push(PatternToken::Slot(
+            name.to_string(),
+            parse_slot_type(slot_type.trim(), types)?,
+        ));
+        rest = &rest[end + 1..];
+    }
+    tokens.extend(
+        rest.split_whitespace()
+            .map(|word| PatternToken::Word(word.to_string())),
+    );
+    Ok(tokens)
+}
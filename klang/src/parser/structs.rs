@@ -1,6 +1,12 @@
-use super::ast::{Command as AstCommand, Program as AstProgram};
+use super::ast::{
+    argument::Value as AstValue, Action as AstAction, Argument as AstArgument, BoundArgument,
+    Command as AstCommand, Program as AstProgram, SourceLocation as AstSourceLocation,
+};
 use super::errors::ParseError;
+use super::passes::dedup::{deduplicate, expand_shared};
+use super::schema::{Arguments, Value};
 use pest_derive::Parser;
+use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::Path;
 
@@ -8,9 +14,82 @@ use std::path::Path;
 #[grammar = "pest/klang.pest"]
 pub struct PestParser;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceLocation {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl std::fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// A command lowered into a primitive from the schema, with typed arguments.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Action {
+    pub name: String,
+    pub arguments: Arguments,
+}
+
+impl Action {
+    pub fn to_ast(&self) -> AstAction {
+        AstAction {
+            name: self.name.clone(),
+            arguments: self
+                .arguments
+                .iter()
+                .map(|(name, value)| AstArgument {
+                    name: name.clone(),
+                    value: Some(match value {
+                        Value::Int(value) => AstValue::Int(*value),
+                        Value::Number(value) => AstValue::Number(*value),
+                        Value::Text(value) => AstValue::Text(value.clone()),
+                    }),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn from_ast(ast: &AstAction) -> Self {
+        Action {
+            name: ast.name.clone(),
+            arguments: ast
+                .arguments
+                .iter()
+                .filter_map(|argument| {
+                    let value = match argument.value.as_ref()? {
+                        AstValue::Int(value) => Value::Int(*value),
+                        AstValue::Number(value) => Value::Number(*value),
+                        AstValue::Text(value) => Value::Text(value.clone()),
+                    };
+                    Some((argument.name.clone(), value))
+                })
+                .collect(),
+        }
+    }
+
+    pub fn argument(&self, name: &str) -> Option<&Value> {
+        self.arguments
+            .iter()
+            .find(|(argument, _)| argument == name)
+            .map(|(_, value)| value)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Node {
     pub text: String,
     pub children: Vec<Node>,
+    /// Where the command or function call appears in the source file.
+    pub location: Option<SourceLocation>,
+    /// Signature of the called function, if this node is a function call.
+    pub function: Option<String>,
+    /// The primitive this command was lowered into, if a schema was used.
+    pub action: Option<Action>,
+    /// For function calls, the value bound to each parameter.
+    pub arguments: Vec<(String, String)>,
 }
 
 impl Node {
@@ -18,6 +97,21 @@ impl Node {
         AstCommand {
             text: self.text.clone(),
             children: self.children.iter().map(|child| child.to_ast()).collect(),
+            shared_index: None,
+            location: self.location.map(|location| AstSourceLocation {
+                line: location.line as u32,
+                column: location.column as u32,
+            }),
+            function: self.function.clone().unwrap_or_default(),
+            action: self.action.as_ref().map(Action::to_ast),
+            arguments: self
+                .arguments
+                .iter()
+                .map(|(name, value)| BoundArgument {
+                    name: name.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
         }
     }
 
@@ -25,9 +119,24 @@ impl Node {
         Node {
             text: ast.text.clone(),
             children: ast.children.iter().map(Node::from_ast).collect(),
+            location: ast.location.as_ref().map(|location| SourceLocation {
+                line: location.line as usize,
+                column: location.column as usize,
+            }),
+            function: Some(ast.function.clone()).filter(|function| !function.is_empty()),
+            action: ast.action.as_ref().map(Action::from_ast),
+            arguments: ast
+                .arguments
+                .iter()
+                .map(|argument| (argument.name.clone(), argument.value.clone()))
+                .collect(),
         }
     }
 
+    pub fn is_function(&self) -> bool {
+        self.function.is_some()
+    }
+
     pub fn to_string(&self, indent: usize) -> String {
         let mut result = format!("{:indent$}{}", " ", self.text, indent = indent);
         if !self.children.is_empty() {
@@ -66,6 +175,7 @@ impl KlangProgram {
     pub fn to_ast(&self) -> AstProgram {
         AstProgram {
             commands: self.program.iter().map(|node| node.to_ast()).collect(),
+            shared: Vec::new(),
         }
     }
 
@@ -82,10 +192,55 @@ impl KlangProgram {
         Ok(())
     }
 
+    /// Like `save_binary`, but stores repeated command subtrees once in a
+    /// shared table. `load_binary` expands the table transparently.
+    pub fn save_binary_deduplicated(&self, path: &Path) -> Result<(), ParseError> {
+        let mut buf = Vec::new();
+        prost::Message::encode(&deduplicate(&self.to_ast()), &mut buf)?;
+        fs::write(path, &buf)?;
+        Ok(())
+    }
+
     pub fn load_binary(path: &Path) -> Result<Self, ParseError> {
         let buf = fs::read(path)?;
         let program = prost::Message::decode(&*buf)?;
-        Ok(KlangProgram::from_ast(&program))
+        Ok(KlangProgram::from_ast(&expand_shared(&program)?))
+    }
+
+    pub fn to_json(&self) -> Result<String, ParseError> {
+        Ok(serde_json::to_string_pretty(&self.to_ast())?)
+    }
+
+    pub fn from_json(input: &str) -> Result<Self, ParseError> {
+        let program: AstProgram = serde_json::from_str(input)?;
+        Ok(KlangProgram::from_ast(&expand_shared(&program)?))
+    }
+
+    pub fn save_json(&self, path: &Path) -> Result<(), ParseError> {
+        fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    pub fn load_json(path: &Path) -> Result<Self, ParseError> {
+        KlangProgram::from_json(&fs::read_to_string(path)?)
+    }
+
+    pub fn to_yaml(&self) -> Result<String, ParseError> {
+        Ok(serde_yaml::to_string(&self.to_ast())?)
+    }
+
+    pub fn from_yaml(input: &str) -> Result<Self, ParseError> {
+        let program: AstProgram = serde_yaml::from_str(input)?;
+        Ok(KlangProgram::from_ast(&expand_shared(&program)?))
+    }
+
+    pub fn save_yaml(&self, path: &Path) -> Result<(), ParseError> {
+        fs::write(path, self.to_yaml()?)?;
+        Ok(())
+    }
+
+    pub fn load_yaml(path: &Path) -> Result<Self, ParseError> {
+        KlangProgram::from_yaml(&fs::read_to_string(path)?)
     }
 
     pub fn save_text(&self, path: &Path) -> Result<(), ParseError> {
@@ -115,3 +270,38 @@ impl std::fmt::Display for KlangProgram {
         write!(f, "{}", self.to_text())
     }
 }
+
+/// A `test "name" { ... }` block, compiled to run on the simulated robot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestCase {
+    pub name: String,
+    pub location: Option<SourceLocation>,
+    pub steps: Vec<TestStep>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TestStep {
+    /// Commands and calls to run, expanded like a program.
+    Run(Vec<Node>),
+    Expect {
+        expectation: Expectation,
+        location: Option<SourceLocation>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expectation {
+    /// The text of the commands run since the previous list, in order.
+    Commands(Vec<String>),
+    /// The position of a joint, named by its action arguments.
+    Joint {
+        key: String,
+        position: f64,
+    },
+    Gripper {
+        key: String,
+        open: bool,
+    },
+    /// Seconds simulated since the test started.
+    Elapsed(f64),
+}
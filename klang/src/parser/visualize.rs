@@ -0,0 +1,129 @@
+use super::structs::{KlangProgram, Node};
+
+fn label(node: &Node) -> String {
+    match node.location {
+        Some(location) => format!("{}\n({})", node.text, location),
+        None => node.text.clone(),
+    }
+}
+
+pub(super) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_dot(node: &Node, parent: &str, next_id: &mut usize, result: &mut String) {
+    let id = format!("n{}", next_id);
+    *next_id += 1;
+    let shape = if node.is_function() { "folder" } else { "box" };
+    let text = label(node)
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    result.push_str(&format!(
+        "    {} [shape={}, label=\"{}\"];\n    {} -> {};\n",
+        id, shape, text, parent, id
+    ));
+    for child in &node.children {
+        write_dot(child, &id, next_id, result);
+    }
+}
+
+fn write_mermaid(node: &Node, parent: &str, next_id: &mut usize, result: &mut String) {
+    let id = format!("n{}", next_id);
+    *next_id += 1;
+    let text = label(node)
+        .replace('"', "#quot;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+        .replace('\n', "<br/>");
+    let class = if node.is_function() {
+        ":::function"
+    } else {
+        ""
+    };
+    result.push_str(&format!(
+        "    {}[\"{}\"]{}\n    {} --> {}\n",
+        id, text, class, parent, id
+    ));
+    for child in &node.children {
+        write_mermaid(child, &id, next_id, result);
+    }
+}
+
+fn write_html(node: &Node, indent: usize, result: &mut String) {
+    let location = match node.location {
+        Some(location) => format!(" <span class=\"location\">{}</span>", location),
+        None => String::new(),
+    };
+    let pad = " ".repeat(indent);
+    if node.is_function() {
+        result.push_str(&format!(
+            "{}<li><details open><summary><span class=\"function\">{}</span>{}</summary>\n{}<ul>\n",
+            pad,
+            escape_html(&node.text),
+            location,
+            pad
+        ));
+        for child in &node.children {
+            write_html(child, indent + 2, result);
+        }
+        result.push_str(&format!("{}</ul></details></li>\n", pad));
+    } else {
+        result.push_str(&format!(
+            "{}<li><span class=\"command\">{}</span>{}</li>\n",
+            pad,
+            escape_html(&node.text),
+            location
+        ));
+    }
+}
+
+const HTML_STYLE: &str = "body { font-family: sans-serif; }
+ul { list-style: none; padding-left: 1.5em; }
+summary { cursor: pointer; }
+.function { font-weight: bold; }
+.command { font-family: monospace; }
+.location { color: #888; font-size: 0.8em; margin-left: 0.5em; }";
+
+impl KlangProgram {
+    /// Renders the expanded program tree as a Graphviz digraph.
+    pub fn to_dot(&self) -> String {
+        let mut result =
+            String::from("digraph program {\n    root [shape=ellipse, label=\"program\"];\n");
+        let mut next_id = 0;
+        for node in &self.program {
+            write_dot(node, "root", &mut next_id, &mut result);
+        }
+        result.push_str("}\n");
+        result
+    }
+
+    /// Renders the expanded program tree as a Mermaid flowchart.
+    pub fn to_mermaid(&self) -> String {
+        let mut result = String::from(
+            "flowchart TD\n    classDef function font-weight:bold\n    root([\"program\"])\n",
+        );
+        let mut next_id = 0;
+        for node in &self.program {
+            write_mermaid(node, "root", &mut next_id, &mut result);
+        }
+        result
+    }
+
+    /// Renders the expanded program tree as a standalone HTML page, with
+    /// function calls as collapsible sections.
+    pub fn to_html(&self) -> String {
+        let mut result = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Klang program</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<ul>\n",
+            HTML_STYLE
+        );
+        for node in &self.program {
+            write_html(node, 0, &mut result);
+        }
+        result.push_str("</ul>\n</body>\n</html>\n");
+        result
+    }
+}
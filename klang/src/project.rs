@@ -0,0 +1,404 @@
+use crate::executor::checkpoint::fnv1a;
+use crate::parser::analysis::top_level_definitions;
+use crate::parser::errors::ParseError;
+use crate::parser::robot::RobotDescription;
+use crate::parser::schema::PrimitiveSchema;
+use crate::parser::structs::{KlangProgram, TestCase};
+use crate::parser::{
+    parse_string_with_schema, tests_from_string, write_program_to_file, OutputFormat,
+};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+pub const MANIFEST_FILE: &str = "klang.toml";
+
+/// Kept in the output directory, with the hash of the inputs of each entry
+/// point as of the build that compiled it.
+const HASHES_FILE: &str = ".klang-hashes.json";
+
+/// The contents of a `klang.toml` file. Paths are relative to the directory
+/// the file is in.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Directories whose `.k` files define the package's functions.
+    #[serde(default)]
+    pub sources: Vec<PathBuf>,
+    /// The programs to compile, each into its own output.
+    pub entry_points: Vec<PathBuf>,
+    /// Directories of `.k` files with functions shared between packages.
+    #[serde(default)]
+    pub libraries: Vec<PathBuf>,
+    #[serde(default = "default_out_dir")]
+    pub out_dir: PathBuf,
+    pub schema: Option<PathBuf>,
+    pub robot: Option<PathBuf>,
+}
+
+fn default_out_dir() -> PathBuf {
+    PathBuf::from("build")
+}
+
+/// An entry point followed by the definitions of every library function,
+/// ready to compile as one source.
+pub struct Linked {
+    pub source: String,
+    /// The line each part of the source starts at, with the file it came
+    /// from and the line it starts at in that file.
+    parts: Vec<(usize, PathBuf, usize)>,
+}
+
+impl Linked {
+    /// Points the locations in an error at the files the lines came from.
+    /// Locations in the entry point are left as they are.
+    pub fn locate(&self, error: ParseError) -> ParseError {
+        let errors = error
+            .errors
+            .into_iter()
+            .map(|mut error| {
+                let Some(location) = &mut error.location else {
+                    return error;
+                };
+                let part = self.parts.iter().rev().find(|part| part.0 <= location.line);
+                if let Some((start, path, first)) = part.filter(|part| part.0 > 1) {
+                    if error.path.is_none() {
+                        location.line = first + location.line - start;
+                        error.path = Some(path.clone());
+                    }
+                }
+                error
+            })
+            .collect();
+        ParseError::from_errors(errors)
+    }
+}
+
+/// What happened to one entry point in a build.
+#[derive(Debug)]
+pub enum BuildStatus {
+    Compiled,
+    /// Skipped, as nothing it is compiled from changed since the last build.
+    UpToDate,
+    Failed(ParseError),
+}
+
+pub struct Project {
+    /// The directory of the manifest.
+    pub root: PathBuf,
+    pub manifest: Manifest,
+}
+
+fn read(path: &Path) -> Result<String, ParseError> {
+    fs::read_to_string(path)
+        .map_err(|e| ParseError::new(format!("Error reading file '{}': {}", path.display(), e)))
+}
+
+/// The path with links and `..` resolved, to tell whether two paths are the
+/// same file.
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Adds the `.k` files in the directory and its subdirectories, in order.
+pub(crate) fn find_sources(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), ParseError> {
+    let entries = fs::read_dir(dir).map_err(|e| {
+        ParseError::new(format!(
+            "Error reading directory '{}': {}",
+            dir.display(),
+            e
+        ))
+    })?;
+    let mut paths: Vec<PathBuf> = entries
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<_, std::io::Error>>()?;
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            find_sources(&path, files)?;
+        } else if path.extension().is_some_and(|extension| extension == "k") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+impl Project {
+    pub fn from_toml(root: &Path, input: &str) -> Result<Self, ParseError> {
+        let manifest: Manifest = toml::from_str(input)
+            .map_err(|e| ParseError::new(format!("Error parsing {}: {}", MANIFEST_FILE, e)))?;
+        // Outputs mirror the entry points under the output directory.
+        let outside = |path: &PathBuf| {
+            path.components()
+                .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+        };
+        if let Some(entry) = manifest.entry_points.iter().find(|entry| outside(entry)) {
+            return Err(ParseError::new(format!(
+                "Error parsing {}: entry point '{}' must be a path inside the project",
+                MANIFEST_FILE,
+                entry.display()
+            )));
+        }
+        if manifest.robot.is_some() && manifest.schema.is_none() {
+            return Err(ParseError::new(format!(
+                "Error parsing {}: a robot description needs a schema, as only commands \
+                 lowered into actions can be checked",
+                MANIFEST_FILE
+            )));
+        }
+        Ok(Project {
+            root: root.to_path_buf(),
+            manifest,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ParseError> {
+        let root = path.parent().unwrap_or(Path::new(""));
+        Project::from_toml(root, &read(path)?)
+    }
+
+    /// Loads the manifest in the directory, or else in the nearest directory
+    /// above it that has one. The empty path is the current directory.
+    pub fn find(dir: &Path) -> Result<Option<Self>, ParseError> {
+        let mut relative = dir.to_path_buf();
+        for absolute in Path::new(".").join(dir).canonicalize()?.ancestors() {
+            if absolute.join(MANIFEST_FILE).is_file() {
+                return Project::load(&relative.join(MANIFEST_FILE)).map(Some);
+            }
+            relative.push("..");
+        }
+        Ok(None)
+    }
+
+    /// A path from the manifest, relative to the current directory.
+    pub fn path(&self, path: &Path) -> PathBuf {
+        self.root.join(path)
+    }
+
+    pub fn entry_points(&self) -> Vec<PathBuf> {
+        self.manifest
+            .entry_points
+            .iter()
+            .map(|entry| self.path(entry))
+            .collect()
+    }
+
+    pub fn schema_path(&self) -> Option<PathBuf> {
+        self.manifest
+            .schema
+            .as_ref()
+            .map(|schema| self.path(schema))
+    }
+
+    pub fn robot_path(&self) -> Option<PathBuf> {
+        self.manifest.robot.as_ref().map(|robot| self.path(robot))
+    }
+
+    pub fn load_schema(&self) -> Result<Option<PrimitiveSchema>, ParseError> {
+        self.schema_path()
+            .map(|path| PrimitiveSchema::load(&path))
+            .transpose()
+    }
+
+    pub fn load_robot(
+        &self,
+        schema: Option<&PrimitiveSchema>,
+    ) -> Result<Option<RobotDescription>, ParseError> {
+        let robot = self
+            .robot_path()
+            .map(|path| RobotDescription::load(&path))
+            .transpose()?;
+        if let (Some(robot), Some(schema)) = (&robot, schema) {
+            robot.check_schema(schema)?;
+        }
+        Ok(robot)
+    }
+
+    /// The `.k` files in the source and library directories, other than the
+    /// entry points.
+    pub fn library_files(&self) -> Result<Vec<PathBuf>, ParseError> {
+        let mut files = Vec::new();
+        for dir in self.manifest.sources.iter().chain(&self.manifest.libraries) {
+            find_sources(&self.path(dir), &mut files)?;
+        }
+        // Directories may be listed twice, or inside one another.
+        let mut seen: BTreeSet<PathBuf> =
+            self.entry_points().iter().map(|e| canonical(e)).collect();
+        files.retain(|file| seen.insert(canonical(file)));
+        Ok(files)
+    }
+
+    /// Every file that a build reads.
+    pub fn files(&self) -> Result<Vec<PathBuf>, ParseError> {
+        let mut files = vec![self.root.join(MANIFEST_FILE)];
+        files.extend(self.entry_points());
+        files.extend(self.library_files()?);
+        files.extend(self.schema_path());
+        files.extend(self.robot_path());
+        Ok(files)
+    }
+
+    /// A path in the project, relative to its root.
+    fn relative<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.root).unwrap_or(path)
+    }
+
+    /// Where an entry point is compiled to: its path in the project, under
+    /// the output directory, so entry points with the same file name in
+    /// different directories do not overwrite each other.
+    pub fn output_path(&self, entry: &Path, format: OutputFormat) -> PathBuf {
+        self.path(&self.manifest.out_dir)
+            .join(self.relative(entry).with_extension(format.extension()))
+    }
+
+    /// Joins a program, usually an entry point, with the functions defined in
+    /// the other library files. A function may only be defined once across
+    /// all of them.
+    pub fn link(&self, entry: &Path) -> Result<Linked, ParseError> {
+        let mut source = read(entry)?;
+        let mut defined_in: BTreeMap<String, PathBuf> = BTreeMap::new();
+        for (signature, _) in top_level_definitions(&source)? {
+            defined_in.insert(signature, entry.to_path_buf());
+        }
+        let mut parts = vec![(1, entry.to_path_buf(), 1)];
+        for file in self
+            .library_files()?
+            .into_iter()
+            .filter(|file| canonical(file) != canonical(entry))
+        {
+            let library = read(&file)?;
+            let definitions = top_level_definitions(&library)
+                .map_err(|e| ParseError::new(format!("{}: {}", file.display(), e)))?;
+            for (signature, span) in definitions {
+                if let Some(other) = defined_in.get(&signature) {
+                    return Err(ParseError::new(format!(
+                        "Function '{}' is defined in both {} and {}",
+                        signature,
+                        other.display(),
+                        file.display()
+                    )));
+                }
+                defined_in.insert(signature, file.clone());
+                if !source.ends_with('\n') {
+                    source.push('\n');
+                }
+                let start = source.matches('\n').count() + 1;
+                let first = library[..span.start].matches('\n').count() + 1;
+                parts.push((start, file.clone(), first));
+                source.push_str(&library[span.start..span.end]);
+            }
+        }
+        source.push('\n');
+        Ok(Linked { source, parts })
+    }
+
+    pub fn compile(
+        &self,
+        entry: &Path,
+        schema: Option<&PrimitiveSchema>,
+        robot: Option<&RobotDescription>,
+    ) -> Result<KlangProgram, ParseError> {
+        let linked = self.link(entry)?;
+        let program =
+            parse_string_with_schema(&linked.source, schema).map_err(|e| linked.locate(e))?;
+        if let Some(robot) = robot {
+            program.check_safety(robot).map_err(|e| linked.locate(e))?;
+        }
+        Ok(program)
+    }
+
+    /// The test blocks of a file, which can call the functions of the
+    /// library files.
+    pub fn tests(
+        &self,
+        file: &Path,
+        schema: Option<&PrimitiveSchema>,
+    ) -> Result<Vec<TestCase>, ParseError> {
+        let linked = self.link(file)?;
+        tests_from_string(&linked.source, schema).map_err(|e| linked.locate(e))
+    }
+
+    /// Identifies everything an output is compiled from, so it only needs
+    /// to be compiled again when this changes.
+    fn input_hash(
+        &self,
+        entry: &Path,
+        format: OutputFormat,
+        deduplicate: bool,
+    ) -> Result<u64, ParseError> {
+        let options = format!(
+            "{} {:?} {}\n",
+            env!("CARGO_PKG_VERSION"),
+            format,
+            deduplicate
+        );
+        let mut inputs = options.into_bytes();
+        let files = [entry.to_path_buf()]
+            .into_iter()
+            .chain(self.library_files()?)
+            .chain(self.schema_path())
+            .chain(self.robot_path());
+        for file in files {
+            // The same project is found from any directory inside it.
+            let name = self.relative(&file);
+            inputs.extend(name.display().to_string().bytes());
+            inputs.push(0);
+            inputs.extend(read(&file)?.bytes());
+            inputs.push(0);
+        }
+        Ok(fnv1a(&inputs))
+    }
+
+    /// Compiles every entry point into the output directory, skipping those
+    /// whose output is up to date.
+    pub fn build(
+        &self,
+        format: OutputFormat,
+        deduplicate: bool,
+    ) -> Result<Vec<(PathBuf, BuildStatus)>, ParseError> {
+        let schema = self.load_schema()?;
+        let robot = self.load_robot(schema.as_ref())?;
+        let out_dir = self.path(&self.manifest.out_dir);
+        fs::create_dir_all(&out_dir)?;
+        let hashes_path = out_dir.join(HASHES_FILE);
+        let previous: BTreeMap<String, u64> = match fs::read_to_string(&hashes_path) {
+            Ok(hashes) => serde_json::from_str(&hashes).unwrap_or_default(),
+            Err(_) => BTreeMap::new(),
+        };
+
+        let mut hashes = BTreeMap::new();
+        let mut results = Vec::new();
+        for entry in self.entry_points() {
+            let output = self.output_path(&entry, format);
+            let key = self.relative(&entry).display().to_string();
+            let hash = self.input_hash(&entry, format, deduplicate)?;
+            if output.exists() && previous.get(&key) == Some(&hash) {
+                hashes.insert(key, hash);
+                results.push((entry, BuildStatus::UpToDate));
+                continue;
+            }
+            let result = self
+                .compile(&entry, schema.as_ref(), robot.as_ref())
+                .and_then(|program| {
+                    if let Some(parent) = output.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    if deduplicate && format == OutputFormat::Binary {
+                        program.save_binary_deduplicated(&output)
+                    } else {
+                        write_program_to_file(&program, &output, format)
+                    }
+                });
+            match result {
+                Ok(()) => {
+                    hashes.insert(key, hash);
+                    results.push((entry, BuildStatus::Compiled));
+                }
+                Err(e) => results.push((entry, BuildStatus::Failed(e))),
+            }
+        }
+        fs::write(&hashes_path, serde_json::to_string_pretty(&hashes)?)?;
+        Ok(results)
+    }
+}
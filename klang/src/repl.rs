@@ -0,0 +1,214 @@
+use crate::executor::sim::SimBackend;
+use crate::executor::Executor;
+use crate::parser::analysis::{self, write_nodes};
+use crate::parser::errors::ParseError;
+use crate::parser::parse_string_with_schema;
+use crate::parser::robot::RobotDescription;
+use crate::parser::schema::PrimitiveSchema;
+use crate::parser::structs::KlangProgram;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+pub const HELP: &str =
+    "Enter function definitions, commands and calls. Blocks continue until their braces are closed.
+  :funcs          List the defined functions
+  :expand <call>  Show the commands a call expands to
+  :load <file>    Add the functions defined in a source file
+  :sim [on|off]   Run inputs on the simulated robot instead of showing their expansion
+  :reset          Forget all functions and reset the simulated robot
+  :help           Show this message
+  :quit           Leave";
+
+/// The state of an interactive session. Functions defined in one input can
+/// be called from the next, and a function defined again replaces the old
+/// definition.
+#[derive(Default)]
+pub struct Session {
+    /// The source of each top-level definition, by signature.
+    definitions: Vec<(String, String)>,
+    schema: Option<PrimitiveSchema>,
+    robot: Option<RobotDescription>,
+    /// Runs inputs on the simulated robot, keeping its state between inputs.
+    executor: Option<Executor<SimBackend>>,
+}
+
+/// Whether the input has a block that is still open, so more lines are
+/// needed before it can be evaluated.
+pub fn is_incomplete(input: &str) -> bool {
+    let mut depth = 0;
+    for line in input.lines() {
+        let code = line.split("//").next().unwrap_or_default();
+        depth += code.matches('{').count() as i64 - code.matches('}').count() as i64;
+    }
+    depth > 0
+}
+
+impl Session {
+    pub fn new(schema: Option<PrimitiveSchema>, robot: Option<RobotDescription>) -> Self {
+        Session {
+            schema,
+            robot,
+            ..Default::default()
+        }
+    }
+
+    pub fn signatures(&self) -> impl Iterator<Item = &str> {
+        self.definitions
+            .iter()
+            .map(|(signature, _)| signature.as_str())
+    }
+
+    pub fn is_simulating(&self) -> bool {
+        self.executor.is_some()
+    }
+
+    pub fn set_simulating(&mut self, simulating: bool) {
+        if !simulating {
+            self.executor = None;
+        } else if self.executor.is_none() {
+            let mut executor = Executor::new(SimBackend::default());
+            if let Some(robot) = &self.robot {
+                executor.set_robot(robot.clone());
+            }
+            self.executor = Some(executor);
+        }
+    }
+
+    /// Evaluates one complete input, returning what to print.
+    pub fn eval(&mut self, input: &str) -> Result<String, Box<dyn Error>> {
+        let trimmed = input.trim();
+        let Some(command) = trimmed.strip_prefix(':') else {
+            return self.eval_source(input);
+        };
+        let (command, argument) = command.split_once(' ').unwrap_or((command, ""));
+        let argument = argument.trim();
+        match command {
+            "help" | "h" => Ok(format!("{}\n", HELP)),
+            "funcs" | "f" => Ok(self
+                .signatures()
+                .map(|signature| format!("> {}\n", signature))
+                .collect()),
+            "expand" | "e" => {
+                let call = argument.trim_start_matches('"').trim();
+                let program = self.compile(&format!("\" {}\n", call))?;
+                Ok(tree_text(&program))
+            }
+            "load" | "l" => {
+                let source = fs::read_to_string(Path::new(argument))
+                    .map_err(|e| format!("Error reading file '{}': {}", argument, e))?;
+                self.compile(&source)?;
+                let loaded = self.define(&source)?;
+                Ok(format!(
+                    "Loaded {} functions from {}\n",
+                    loaded.len(),
+                    argument
+                ))
+            }
+            "sim" => {
+                let simulating = match argument {
+                    "" => !self.is_simulating(),
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(format!("Expected 'on' or 'off', got '{}'", argument).into()),
+                };
+                self.set_simulating(simulating);
+                Ok(if simulating {
+                    "Running inputs on the simulated robot\n".to_string()
+                } else {
+                    "Showing the expansion of inputs\n".to_string()
+                })
+            }
+            "reset" => {
+                self.definitions.clear();
+                if self.is_simulating() {
+                    self.set_simulating(false);
+                    self.set_simulating(true);
+                }
+                Ok("Forgot all functions\n".to_string())
+            }
+            _ => Err(format!("Unknown command ':{}', try :help", command).into()),
+        }
+    }
+
+    fn eval_source(&mut self, input: &str) -> Result<String, Box<dyn Error>> {
+        let program = self.compile(input)?;
+        let defined = self.define(input)?;
+        if program.program.is_empty() {
+            return Ok(defined
+                .iter()
+                .map(|signature| format!("Defined {}\n", signature))
+                .collect());
+        }
+        let Some(executor) = &mut self.executor else {
+            return Ok(tree_text(&program));
+        };
+        let start = executor.backend().history().len();
+        let result = executor.run(&program);
+        let mut output: String = executor.backend().history()[start..]
+            .iter()
+            .map(|command| format!("ran: {}\n", command))
+            .collect();
+        let summary = result?;
+        output.push_str(&format!(
+            "{} commands, {:.1}s simulated in total\n",
+            summary.commands,
+            executor.backend().elapsed()
+        ));
+        Ok(output)
+    }
+
+    /// Compiles the input with the functions defined so far, letting the
+    /// input's own definitions replace them.
+    fn compile(&self, input: &str) -> Result<KlangProgram, ParseError> {
+        let redefined = top_level_definitions(input)?;
+        // The input goes first so errors point at its own lines.
+        let mut source = input.to_string();
+        for (signature, definition) in &self.definitions {
+            if !redefined.iter().any(|(new, _)| new == signature) {
+                source.push('\n');
+                source.push_str(definition);
+            }
+        }
+        source.push('\n');
+        let program = parse_string_with_schema(&source, self.schema.as_ref())?;
+        if let Some(robot) = &self.robot {
+            program.check_safety(robot)?;
+        }
+        Ok(program)
+    }
+
+    /// Adds the top-level definitions of source that is known to compile,
+    /// returning their signatures.
+    fn define(&mut self, source: &str) -> Result<Vec<String>, ParseError> {
+        let definitions = top_level_definitions(source)?;
+        let signatures = definitions
+            .iter()
+            .map(|(signature, _)| signature.clone())
+            .collect();
+        for (signature, definition) in definitions {
+            match self
+                .definitions
+                .iter_mut()
+                .find(|(old, _)| *old == signature)
+            {
+                Some(old) => old.1 = definition,
+                None => self.definitions.push((signature, definition)),
+            }
+        }
+        Ok(signatures)
+    }
+}
+
+fn top_level_definitions(source: &str) -> Result<Vec<(String, String)>, ParseError> {
+    Ok(analysis::top_level_definitions(source)?
+        .into_iter()
+        .map(|(signature, span)| (signature, source[span.start..span.end].to_string()))
+        .collect())
+}
+
+fn tree_text(program: &KlangProgram) -> String {
+    let mut result = String::new();
+    write_nodes(&program.program, 0, &mut result);
+    result
+}
@@ -1,12 +1,1064 @@
 #[cfg(test)]
 mod tests {
-    use klang::parser::parse_file;
+    use klang::executor::checkpoint::Checkpoint;
+    use klang::executor::control::ControlHandle;
+    use klang::executor::debugger::{
+        Breakpoint, DebugCommand, Debugger, PauseReason, PauseState, PromptHandler,
+    };
+    use klang::executor::errors::ExecutionError;
+    use klang::executor::sim::{SimBackend, SimConfig};
+    use klang::executor::trace::{ReplayBackend, Trace};
+    use klang::executor::{Event, Executor, Outcome, RobotBackend, RunStatus};
+    use klang::parser::analysis::Analysis;
+    use klang::parser::cst::{SyntaxKind, SyntaxTree};
+    use klang::parser::docs::Reference;
+    use klang::parser::errors::ParseError;
+    use klang::parser::estimate::DurationModel;
+    use klang::parser::rename::{apply_edits, RenameTarget};
+    use klang::parser::robot::RobotDescription;
+    use klang::parser::schema::{DurationEstimate, PrimitiveSchema, Value};
+    use klang::parser::structs::{KlangProgram, Node};
+    use klang::parser::OutputFormat;
+    use klang::parser::{
+        call_graph_from_file, dump_ir_string, format_string, parse_file, parse_file_with_schema,
+        parse_string, parse_string_with_schema, tests_from_string,
+    };
+    use klang::project::{BuildStatus, Project};
+    use klang::repl::{is_incomplete, Session};
+    use klang::testing::{parse_tree_text, run_test, tree_diff, GoldenFormat, GoldenTest};
+    use klang::watch::Watcher;
+    use std::cell::RefCell;
     use std::path::Path;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct RecordingBackend {
+        commands: Vec<String>,
+        fail_on: Option<String>,
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl RobotBackend for RecordingBackend {
+        fn execute(&mut self, command: &Node) -> Result<Outcome, ExecutionError> {
+            self.commands.push(command.text.clone());
+            if self.fail_on.as_deref() == Some(command.text.as_str()) {
+                return Ok(Outcome::Failure("stalled".to_string()));
+            }
+            Ok(Outcome::Success)
+        }
+    }
+
+    /// Pauses the run on the 2nd command and cancels it on the 3rd, as an
+    /// operator would from another thread.
+    #[derive(Default)]
+    struct InterruptingBackend {
+        handle: Option<ControlHandle>,
+        commands: Vec<String>,
+        stopped: bool,
+    }
+
+    impl RobotBackend for InterruptingBackend {
+        fn execute(&mut self, command: &Node) -> Result<Outcome, ExecutionError> {
+            self.commands.push(command.text.clone());
+            if let Some(handle) = &self.handle {
+                match self.commands.len() {
+                    2 => {
+                        handle.pause();
+                        let handle = handle.clone();
+                        std::thread::spawn(move || {
+                            std::thread::sleep(std::time::Duration::from_millis(10));
+                            handle.resume();
+                        });
+                    }
+                    3 => handle.cancel(),
+                    _ => {}
+                }
+            }
+            Ok(Outcome::Success)
+        }
+
+        fn stop(&mut self) -> Result<(), ExecutionError> {
+            self.stopped = true;
+            Ok(())
+        }
+    }
 
     #[test]
     fn test_parse_clean_up_cans() {
         let file_path = Path::new("../examples/simple.k");
-        let parsed_file = parse_file(&file_path);
+        let parsed_file = parse_file(file_path);
         assert!(parsed_file.is_ok());
     }
+
+    #[test]
+    fn test_deduplicated_binary_roundtrip() {
+        let program = parse_string(
+            "> wave {\n    move arm up\n    move arm down\n}\n\n\" wave\n\" wave\n\" wave\n",
+        )
+        .unwrap();
+        let dir = std::env::temp_dir();
+        let plain_path = dir.join("klang_test_plain.ko");
+        let shared_path = dir.join("klang_test_shared.ko");
+
+        program.save_binary(&plain_path).unwrap();
+        program.save_binary_deduplicated(&shared_path).unwrap();
+        let plain_len = std::fs::metadata(&plain_path).unwrap().len();
+        let shared_len = std::fs::metadata(&shared_path).unwrap().len();
+        assert!(shared_len < plain_len);
+
+        let loaded = KlangProgram::load_binary(&shared_path).unwrap();
+        assert_eq!(loaded.to_text(), program.to_text());
+    }
+
+    #[test]
+    fn test_format_and_decompile() {
+        let source = std::fs::read_to_string("../examples/simple.k").unwrap();
+        let messy = ">   wave [arm]   arm{\n\
+            >wave joint [joint] twice {\n\
+            move joint [ joint ] on the [arm] arm to 90\n\
+            \t\tmove joint [joint] on the [arm] arm to 0\n}\n\n\n\
+            \"wave joint [1] twice\n  \" wave joint [2] twice\n\" wave joint [3] twice\n}\n\
+            > wave both arms {\n\" wave [right] arm\n\" wave [left] arm\n}\n\" wave both arms";
+        assert_eq!(format_string(messy).unwrap(), source);
+
+        let commented =
+            "// Greets.\n>greet{ // header\n  say hi // inline\n\n\n  /* last */\n}\n\"greet";
+        let formatted = format_string(commented).unwrap();
+        assert_eq!(
+            formatted,
+            "// Greets.\n> greet { // header\n    say hi // inline\n\n    /* last */\n}\n\n\" greet\n"
+        );
+        assert_eq!(format_string(&formatted).unwrap(), formatted);
+        let program = parse_string("move arm // raise\nmove leg\n").unwrap();
+        assert_eq!(program.program.len(), 2);
+        assert_eq!(
+            parse_string(commented).unwrap().to_text(),
+            parse_string(&formatted).unwrap().to_text()
+        );
+
+        let program = parse_string(&source).unwrap();
+        let decompiled = program.decompile().unwrap();
+        assert!(decompiled.starts_with("> wave both arms {\n    \" wave [right] arm\n"));
+        assert!(decompiled.contains("    move joint [joint] on the [arm] arm to 90\n"));
+        assert_eq!(
+            parse_string(&decompiled).unwrap().to_text(),
+            program.to_text()
+        );
+    }
+
+    #[test]
+    fn test_syntax_tree_is_lossless() {
+        let source = std::fs::read_to_string("../examples/simple.k").unwrap();
+        let messy =
+            "// Top.\r\n>  wave [ arm ]{ /* a */\r\n\tmove [arm]  up // b\r\n\r\n}\n\" wave [left]";
+        for input in [source.as_str(), messy] {
+            let tree = SyntaxTree::parse(input).unwrap();
+            assert_eq!(tree.root.to_string(), input);
+        }
+
+        let tree = SyntaxTree::parse(messy).unwrap();
+        let comments: Vec<&str> = tree
+            .root
+            .descendant_tokens()
+            .into_iter()
+            .filter(|token| token.kind.is_comment())
+            .map(|token| token.text.as_str())
+            .collect();
+        assert_eq!(comments, vec!["// Top.", "/* a */", "// b"]);
+
+        let function = tree.root.child(SyntaxKind::FunctionDef).unwrap();
+        let name = function.child(SyntaxKind::Name).unwrap();
+        assert_eq!(&messy[name.span.start..name.span.end], "wave [ arm ]");
+        assert_eq!(tree.line_col(function.span.start), (2, 1));
+        assert_eq!(
+            parse_string(messy).unwrap().to_text(),
+            parse_string("> wave [arm] {\n    move [arm] up\n}\n\" wave [left]\n")
+                .unwrap()
+                .to_text()
+        );
+    }
+
+    #[test]
+    fn test_analysis_for_editors() {
+        let source = std::fs::read_to_string("../examples/simple.k").unwrap();
+        let analysis = Analysis::new(&source);
+        assert!(analysis.diagnostics.is_empty());
+        let signatures: Vec<&str> = analysis
+            .definitions
+            .iter()
+            .map(|definition| definition.signature.as_str())
+            .collect();
+        assert_eq!(
+            signatures,
+            vec![
+                "wave [arm] arm",
+                "wave joint [joint] twice",
+                "wave both arms"
+            ]
+        );
+        assert_eq!(analysis.definitions[1].parent, Some(0));
+
+        let call = source.find("\" wave [left] arm").unwrap() + 3;
+        assert_eq!(analysis.definition_at(call), Some(0));
+        assert_eq!(analysis.references(0).count(), 2);
+        let expansion = analysis.expansion_at(call).unwrap();
+        assert!(expansion.starts_with("wave left arm {\n    wave joint 1 twice {\n"));
+        let inner = source.find("wave joint [joint]").unwrap();
+        assert!(analysis
+            .expansion_at(inner)
+            .unwrap()
+            .contains("\n    move joint [joint] on the [arm] arm to 90\n"));
+        assert_eq!(
+            analysis.completions()[1].snippet,
+            "wave joint ${1:joint} twice"
+        );
+
+        let analysis = Analysis::new("> greet {\n    say hi\n}\n\" great\n");
+        assert_eq!(analysis.diagnostics.len(), 1);
+        assert_eq!(analysis.diagnostics[0].message, "Function not found: great");
+        assert_eq!(analysis.diagnostics[0].location.line, 4);
+        let analysis = Analysis::new("> greet {\n    say hi\n");
+        assert!(analysis.tree.is_none());
+        assert_eq!(analysis.diagnostics[0].location.line, 3);
+
+        // A call that several definitions match resolves to the first one,
+        // both when compiling and in the editor.
+        for (first, second) in [("arm", "side"), ("side", "arm")] {
+            let source = format!(
+                "> wave [{}] arm {{\n    raise it\n}}\n> wave [{}] arm {{\n    lower it\n}}\n\" wave [left] arm\n",
+                first, second
+            );
+            let program = parse_string(&source).unwrap();
+            assert_eq!(
+                program.program[0].function.as_deref(),
+                Some(format!("wave [{}] arm", first).as_str())
+            );
+            assert_eq!(Analysis::new(&source).calls[0].definition, Some(0));
+            let graph = klang::parser::call_graph_from_string(&source).unwrap();
+            assert_eq!(graph.edges[0].callee, format!("wave [{}] arm", first));
+        }
+    }
+
+    #[test]
+    fn test_rename() {
+        let source = std::fs::read_to_string("../examples/simple.k").unwrap();
+        let analysis = Analysis::new(&source);
+        let wave = analysis.find_definition("wave  [arm] arm").unwrap();
+        let edits = analysis.rename_function(wave, "[arm] arm waves").unwrap();
+        let renamed = apply_edits(&source, &edits);
+        assert!(renamed.starts_with("> [arm] arm waves {\n"));
+        assert!(renamed.contains("    \" [right] arm waves\n    \" [left] arm waves\n"));
+        assert_eq!(
+            parse_string(&renamed).unwrap().to_text(),
+            parse_string(&source)
+                .unwrap()
+                .to_text()
+                .replace("wave right arm", "right arm waves")
+                .replace("wave left arm", "left arm waves")
+        );
+        assert!(analysis.rename_function(wave, "wave [side] arm").is_err());
+
+        let edits = analysis.rename_parameter(1, "joint", "j").unwrap();
+        let renamed = apply_edits(&source, &edits);
+        assert!(renamed.contains("> wave joint [j] twice {\n        move joint [j] on the [arm]"));
+        assert_eq!(edits.len(), 3);
+
+        // The nested function's own parameter shadows the outer one.
+        let nested = "> outer [x] {\n    > inner [x] {\n        say [x]\n    }\n    say [x]\n    \" inner [x]\n}\n\" outer [1]\n";
+        let analysis = Analysis::new(nested);
+        let use_in_body = nested.find("say [x]\n    \"").unwrap() + 5;
+        let (target, _) = analysis.rename_target(use_in_body).unwrap();
+        assert_eq!(
+            target,
+            RenameTarget::Parameter {
+                definition: 0,
+                name: "x".to_string()
+            }
+        );
+        let renamed = apply_edits(nested, &analysis.rename_at(use_in_body, "y").unwrap());
+        assert_eq!(
+            renamed,
+            "> outer [y] {\n    > inner [x] {\n        say [x]\n    }\n    say [y]\n    \" inner [y]\n}\n\" outer [1]\n"
+        );
+        let captured = "> outer [x] {\n    > inner [y] {\n        say [x]\n    }\n}\n";
+        assert!(Analysis::new(captured)
+            .rename_parameter(0, "x", "y")
+            .is_err());
+        let capturing = "> outer [a] {\n    > inner [b] {\n        say [a] and [b]\n    }\n    \
+                         \" inner [x]\n}\n\" outer [y]\n";
+        let error = Analysis::new(capturing)
+            .rename_parameter(1, "b", "a")
+            .unwrap_err();
+        assert!(error.message.starts_with("'a' is already used in the body"));
+
+        let conflict = "> greet {\n    say hi\n}\n> wave {\n    say bye\n}\n\" greet\n";
+        let error = Analysis::new(conflict)
+            .rename_function(1, "greet")
+            .unwrap_err();
+        assert_eq!(error.message, "A function is already defined as: greet");
+        let conflict =
+            "> wave {\n    say bye\n}\n> greet [who] {\n    say hi\n}\n\" greet everyone\n";
+        let error = Analysis::new(conflict)
+            .rename_function(0, "greet everyone")
+            .unwrap_err();
+        assert_eq!(
+            error.message,
+            "The rename would change which function a call resolves to"
+        );
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let source = "/// Says hello.\n///\n/// Twice.\n> greet [who] {\n    say hi [who]\n}\n\n\
+            /// Not attached.\n\n// Also not.\n> wave {\n    \" greet [you]\n}\n\n\" wave\n";
+        let analysis = Analysis::new(source);
+        assert_eq!(analysis.definitions[0].doc, "Says hello.\n\nTwice.");
+        assert_eq!(analysis.definitions[1].doc, "");
+        assert!(dump_ir_string(source)
+            .unwrap()
+            .contains("doc: \"Says hello.\\n\\nTwice.\""));
+        assert_eq!(format_string(source).unwrap(), source);
+
+        let mut reference = Reference::new("Motions");
+        reference.add_source("greet.k", source).unwrap();
+        let markdown = reference.to_markdown();
+        assert!(markdown.starts_with(
+            "# Motions\n\n## greet.k\n\n### `greet [who]`\n\nSays hello.\n\nTwice.\n\n"
+        ));
+        assert!(markdown.contains("Parameters: `who`\n\nCalled by: `wave`\n\nExample:\n\n```\ngreet you {\n    say hi you\n}\n```\n"));
+        assert!(markdown.contains("### `wave`\n\nCalled by: the program\n"));
+        assert!(reference
+            .to_html()
+            .contains("<h3><code>greet [who]</code></h3>\n<p>Says hello.</p>\n<p>Twice.</p>\n"));
+    }
+
+    #[test]
+    fn test_call_graph() {
+        let graph = call_graph_from_file(Path::new("../examples/simple.k")).unwrap();
+        assert_eq!(graph.functions.len(), 3);
+
+        let edge = graph
+            .edges
+            .iter()
+            .find(|edge| edge.caller == "wave both arms")
+            .unwrap();
+        assert_eq!(edge.callee, "wave [arm] arm");
+        assert_eq!(edge.count, 2);
+        assert_eq!(edge.bindings, vec!["arm=right", "arm=left"]);
+        assert!(graph
+            .to_dot()
+            .contains("\"wave both arms\" -> \"wave [arm] arm\""));
+    }
+
+    #[test]
+    fn test_tree_export_has_locations() {
+        let program = parse_file(Path::new("../examples/simple.k")).unwrap();
+        let root = &program.program[0];
+        assert_eq!(root.function.as_deref(), Some("wave both arms"));
+        assert_eq!(root.location.map(|l| l.line), Some(17));
+
+        let command = &root.children[0].children[0].children[0];
+        assert!(!command.is_function());
+        assert_eq!(command.location.map(|l| (l.line, l.column)), Some((3, 9)));
+
+        assert!(program.to_dot().contains("wave both arms\\n(17:1)"));
+        assert!(program.to_html().contains("<details open>"));
+    }
+
+    #[test]
+    fn test_json_and_yaml_roundtrip() {
+        let program = parse_file(Path::new("../examples/simple.k")).unwrap();
+
+        let json = program.to_json().unwrap();
+        assert!(json.contains("\"function\": \"wave both arms\""));
+        let from_json = KlangProgram::from_json(&json).unwrap();
+        assert_eq!(from_json.to_text(), program.to_text());
+        assert_eq!(from_json.program[0].location, program.program[0].location);
+
+        let from_yaml = KlangProgram::from_yaml(&program.to_yaml().unwrap()).unwrap();
+        assert_eq!(from_yaml.to_text(), program.to_text());
+    }
+
+    #[test]
+    fn test_primitive_schema_validation() {
+        let schema = PrimitiveSchema::load(Path::new("../examples/primitives.toml")).unwrap();
+        let file_path = Path::new("../examples/simple.k");
+        assert!(parse_file_with_schema(file_path, Some(&schema)).is_ok());
+
+        let error = parse_string_with_schema(
+            "mvoe joint 1 to 90\nmove joint 1 on the left arm to up\n",
+            Some(&schema),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.message,
+            "Unknown command: mvoe joint 1 to 90 (line: 1, column: 1)\n\
+             Invalid argument 'up' for 'angle' of move_joint in command: \
+             move joint 1 on the left arm to up (expected number) (line: 2, column: 1)"
+        );
+        let lines: Vec<usize> = error
+            .errors
+            .iter()
+            .map(|error| error.location.unwrap().line)
+            .collect();
+        assert_eq!(lines, vec![1, 2]);
+
+        for number in ["NaN", "inf", "infinity"] {
+            let command = format!("move joint 1 on the left arm to {}\n", number);
+            assert!(parse_string_with_schema(&command, Some(&schema)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_commands_lowered_into_actions() {
+        let schema = PrimitiveSchema::load(Path::new("../examples/primitives.toml")).unwrap();
+        let program =
+            parse_file_with_schema(Path::new("../examples/simple.k"), Some(&schema)).unwrap();
+        let command = &program.program[0].children[0].children[1].children[0];
+        assert_eq!(command.text, "move joint 2 on the right arm to 90");
+
+        let action = command.action.as_ref().unwrap();
+        assert_eq!(action.name, "move_joint");
+        assert_eq!(action.argument("joint"), Some(&Value::Int(2)));
+        assert_eq!(
+            action.argument("arm"),
+            Some(&Value::Text("right".to_string()))
+        );
+        assert_eq!(action.argument("angle"), Some(&Value::Number(90.0)));
+
+        let reloaded = KlangProgram::from_json(&program.to_json().unwrap()).unwrap();
+        let reloaded_command = &reloaded.program[0].children[0].children[1].children[0];
+        assert_eq!(reloaded_command.action.as_ref(), Some(action));
+    }
+
+    #[test]
+    fn test_duration_estimate() {
+        let schema = PrimitiveSchema::load(Path::new("../examples/primitives.toml")).unwrap();
+        let program = parse_string_with_schema(
+            "> greet {\n    say hello there\n    wait 2 seconds\n}\n\n\
+             move joint 1 on the left arm to 90\n\" greet\n\" greet\n",
+            Some(&schema),
+        )
+        .unwrap();
+
+        let report = program.estimate_duration(&DurationModel::from_schema(&schema));
+        assert_eq!(
+            report.total,
+            DurationEstimate {
+                expected: 9.0,
+                min: 6.0,
+                max: 15.0
+            }
+        );
+        assert_eq!(report.functions.len(), 1);
+        assert_eq!(report.functions[0].function, "greet");
+        assert_eq!(report.functions[0].calls, 2);
+        assert_eq!(report.functions[0].duration.expected, 7.0);
+        assert!(report.unknown.is_empty());
+
+        let mut backwards = parse_string_with_schema("wait 5 seconds\n", Some(&schema)).unwrap();
+        backwards.program[0].action.as_mut().unwrap().arguments[0].1 = Value::Int(-5);
+        let report = backwards.estimate_duration(&DurationModel::from_schema(&schema));
+        assert_eq!(report.total, DurationEstimate::exact(0.0));
+
+        let unlowered = parse_string("move arm up\nwave\n").unwrap();
+        let model = DurationModel::from_toml("[durations]\n\"move arm up\" = 2.5\n").unwrap();
+        let report = unlowered.estimate_duration(&model);
+        assert_eq!(report.total, DurationEstimate::exact(2.5));
+        assert_eq!(report.unknown, ["wave"]);
+    }
+
+    #[test]
+    fn test_joint_limits() {
+        let schema = PrimitiveSchema::load(Path::new("../examples/primitives.toml")).unwrap();
+        let robot = RobotDescription::load(Path::new("../examples/robot.toml")).unwrap();
+        let program =
+            parse_file_with_schema(Path::new("../examples/simple.k"), Some(&schema)).unwrap();
+        assert!(program.check_safety(&robot).is_ok());
+
+        let program = parse_string_with_schema(
+            "> reach {\n    move joint 2 on the left arm to 160\n}\n\n\
+             move joint 1 on the right arm to 720\n\" reach\n",
+            Some(&schema),
+        )
+        .unwrap();
+        let error = program.check_safety(&robot).err().unwrap();
+        assert_eq!(
+            error.message,
+            "Joint 'right_shoulder' target 720 is outside its range [-90, 180] in command: \
+             move joint 1 on the right arm to 720 (line: 5, column: 1)\n\
+             Joint 'elbow' target 160 is outside its range [0, 150] in command: \
+             move joint 2 on the left arm to 160 (line: 2, column: 5)"
+        );
+
+        let mut executor = Executor::new(RecordingBackend::default());
+        executor.set_robot(robot.clone());
+        let error = executor.run(&program).err().unwrap();
+        assert!(error
+            .message
+            .starts_with("Safety check failed: Joint 'right_shoulder' target 720"));
+        assert!(executor.backend().commands.is_empty());
+
+        let mut program =
+            parse_string_with_schema("move joint 1 on the right arm to 90\n", Some(&schema))
+                .unwrap();
+        let action = program.program[0].action.as_mut().unwrap();
+        for (_, value) in action.arguments.iter_mut() {
+            if matches!(value, Value::Number(_)) {
+                *value = Value::Number(f64::NAN);
+            }
+        }
+        let error = program.check_safety(&robot).err().unwrap();
+        assert!(error
+            .message
+            .starts_with("Joint 'right_shoulder' target NaN is outside its range"));
+        assert!(robot.check_schema(&schema).is_ok());
+
+        let float_match = RobotDescription::from_toml(
+            "[[joints]]\nname = \"shoulder\"\nmatch = { joint = 1.0 }\nmax = 90.0\n",
+        )
+        .unwrap();
+        let program =
+            parse_string_with_schema("move joint 1 on the right arm to 120\n", Some(&schema))
+                .unwrap();
+        assert!(program.check_safety(&float_match).is_err());
+
+        let unknown = RobotDescription::from_toml(
+            "[[joints]]\nname = \"shoulder\"\nmatch = { joints = 1 }\nmax = 90.0\n",
+        )
+        .unwrap();
+        assert_eq!(
+            unknown.check_schema(&schema).err().unwrap().message,
+            "Joint 'shoulder' matches on 'joints', which is not an argument of any primitive"
+        );
+    }
+
+    #[test]
+    fn test_executor_runs_commands_in_order() {
+        let program = parse_file(Path::new("../examples/simple.k")).unwrap();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut executor = Executor::new(RecordingBackend::default());
+        let recorded = events.clone();
+        executor.on_event(move |event| {
+            recorded.borrow_mut().push(match event {
+                Event::EnterFunction { path, .. } => format!("enter {:?}", path),
+                Event::ExitFunction { path, .. } => format!("exit {:?}", path),
+                Event::CommandStarted { path, .. } => format!("start {:?}", path),
+                Event::CommandFinished {
+                    completed, total, ..
+                } => format!("done {}/{}", completed, total),
+                Event::Paused { path } => format!("paused {:?}", path),
+                Event::Resumed { path } => format!("resumed {:?}", path),
+            })
+        });
+
+        let summary = executor.run(&program).unwrap();
+        assert_eq!(summary.commands, 12);
+        let backend = executor.into_backend();
+        assert_eq!(backend.commands[0], "move joint 1 on the right arm to 90");
+        assert_eq!(backend.commands[11], "move joint 3 on the left arm to 0");
+
+        let events = events.borrow();
+        assert_eq!(
+            events[..5],
+            [
+                "enter [0]",
+                "enter [0, 0]",
+                "enter [0, 0, 0]",
+                "start [0, 0, 0, 0]",
+                "done 1/12"
+            ]
+        );
+        assert_eq!(events.last().unwrap(), "exit [0]");
+    }
+
+    #[test]
+    fn test_executor_stops_on_failure() {
+        let program = parse_file(Path::new("../examples/simple.k")).unwrap();
+        let mut executor = Executor::new(RecordingBackend {
+            fail_on: Some("move joint 2 on the right arm to 0".to_string()),
+            ..Default::default()
+        });
+        let error = executor.run(&program).err().unwrap();
+        assert_eq!(
+            error.message,
+            "Command failed: move joint 2 on the right arm to 0: stalled (line: 4, column: 9)"
+        );
+        assert_eq!(executor.backend().commands.len(), 4);
+    }
+
+    #[test]
+    fn test_executor_pause_cancel_and_resume() {
+        let program = parse_file(Path::new("../examples/simple.k")).unwrap();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut executor = Executor::new(InterruptingBackend::default());
+        executor.backend_mut().handle = Some(executor.control_handle());
+        let recorded = events.clone();
+        executor.on_event(move |event| match event {
+            Event::Paused { path } => recorded.borrow_mut().push(format!("paused {:?}", path)),
+            Event::Resumed { path } => recorded.borrow_mut().push(format!("resumed {:?}", path)),
+            _ => {}
+        });
+
+        let summary = executor.run(&program).unwrap();
+        assert_eq!(summary.commands, 3);
+        assert_eq!(
+            summary.status,
+            RunStatus::Cancelled {
+                path: vec![0, 0, 1, 1]
+            }
+        );
+        assert!(executor.backend().stopped);
+        assert_eq!(
+            *events.borrow(),
+            ["paused [0, 0, 1, 0]", "resumed [0, 0, 1, 0]"]
+        );
+
+        let mut resumed = Executor::new(RecordingBackend::default());
+        let summary = resumed.resume(&program, &[0, 0, 1, 1]).unwrap();
+        assert_eq!(summary.commands, 9);
+        assert_eq!(summary.status, RunStatus::Completed);
+        assert_eq!(
+            resumed.backend().commands[0],
+            "move joint 2 on the right arm to 0"
+        );
+
+        for path in [&[7][..], &[0, 0, 1, 1, 0]] {
+            let error = resumed.resume(&program, path).err().unwrap();
+            assert!(error.message.starts_with("Invalid resume path"));
+        }
+
+        let mut cancelled = Executor::new(RecordingBackend::default());
+        cancelled.control_handle().cancel();
+        let summary = cancelled.run(&program).unwrap();
+        assert_eq!(summary.commands, 0);
+        assert!(matches!(summary.status, RunStatus::Cancelled { .. }));
+        assert_eq!(
+            cancelled.run(&program).unwrap().status,
+            RunStatus::Completed
+        );
+    }
+
+    #[test]
+    fn test_checkpoint_and_resume() {
+        let program = parse_file(Path::new("../examples/simple.k")).unwrap();
+        let checkpoint_path = std::env::temp_dir().join("klang_test_checkpoint.json");
+        let mut sim = SimBackend::default();
+        sim.inject_failure_at(5, "robot rebooted");
+        let mut executor = Executor::new(&mut sim);
+        executor.checkpoint_to(&checkpoint_path);
+        assert!(executor.run(&program).is_err());
+
+        let checkpoint = Checkpoint::load(&checkpoint_path).unwrap();
+        assert_eq!(checkpoint.path, vec![0, 0, 2, 0]);
+        assert_eq!(checkpoint.completed, 5);
+        assert_eq!(
+            checkpoint.call_stack[2].arguments,
+            [("joint".to_string(), "3".to_string())]
+        );
+
+        let mut executor = Executor::new(RecordingBackend::default());
+        let summary = executor
+            .resume_from_checkpoint(&program, &checkpoint)
+            .unwrap();
+        assert_eq!(summary.commands, 7);
+        assert_eq!(
+            executor.backend().commands[0],
+            "move joint 3 on the right arm to 0"
+        );
+
+        let other = parse_string("move joint 9 on the left arm to 0\n").unwrap();
+        let error = executor
+            .resume_from_checkpoint(&other, &checkpoint)
+            .err()
+            .unwrap();
+        assert!(error
+            .message
+            .starts_with("Checkpoint is for a different program"));
+    }
+
+    #[test]
+    fn test_sim_backend_tracks_state() {
+        let schema = PrimitiveSchema::load(Path::new("../examples/primitives.toml")).unwrap();
+        let program = parse_string_with_schema(
+            "move joint 1 on the left arm to 90\nopen the left gripper\nwait 2.5 seconds\n\
+             move joint 1 on the left arm to 45\nclose the left gripper\n",
+            Some(&schema),
+        )
+        .unwrap();
+
+        let mut sim = SimBackend::new(SimConfig {
+            joint_velocity: Some(45.0),
+            ..Default::default()
+        });
+        Executor::new(&mut sim).run(&program).unwrap();
+        assert_eq!(sim.joint_position("joint=1 arm=left"), Some(45.0));
+        assert_eq!(sim.grippers().get("arm=left"), Some(&false));
+        assert_eq!(sim.elapsed(), 2.0 + 1.0 + 2.5 + 1.0 + 1.0);
+        assert_eq!(sim.history().len(), 5);
+
+        for velocity in [0.0, -45.0] {
+            let mut sim = SimBackend::new(SimConfig {
+                joint_velocity: Some(velocity),
+                ..Default::default()
+            });
+            Executor::new(&mut sim).run(&program).unwrap();
+            assert_eq!(sim.elapsed(), 1.0 + 1.0 + 2.5 + 1.0 + 1.0);
+        }
+    }
+
+    #[test]
+    fn test_sim_backend_injected_failures() {
+        let program = parse_file(Path::new("../examples/simple.k")).unwrap();
+        let mut sim = SimBackend::default();
+        sim.inject_failure("left arm to 90", "joint stalled");
+        let error = Executor::new(&mut sim).run(&program).err().unwrap();
+        assert!(error
+            .message
+            .starts_with("Command failed: move joint 1 on the left arm to 90: joint stalled"));
+        assert_eq!(sim.history().len(), 7);
+
+        let mut sim = SimBackend::default();
+        sim.inject_failure_at(2, "timeout");
+        assert!(Executor::new(&mut sim).run(&program).is_err());
+        assert_eq!(sim.history().len(), 3);
+    }
+
+    #[test]
+    fn test_trace_record_and_replay() {
+        let schema = PrimitiveSchema::load(Path::new("../examples/primitives.toml")).unwrap();
+        let program =
+            parse_file_with_schema(Path::new("../examples/simple.k"), Some(&schema)).unwrap();
+        let mut sim = SimBackend::default();
+        sim.inject_failure_at(5, "encoder fault");
+
+        let mut executor = Executor::new(&mut sim);
+        executor.record_trace();
+        assert!(executor.run(&program).is_err());
+        let trace = executor.take_trace().unwrap();
+        assert_eq!(trace.entries.len(), 6);
+        assert_eq!(trace.entries[0].path, vec![0, 0, 0, 0]);
+        assert_eq!(trace.entries[0].action.as_ref().unwrap().name, "move_joint");
+        assert_eq!(
+            trace.entries[5].outcome,
+            Outcome::Failure("encoder fault".to_string())
+        );
+
+        let trace = Trace::from_jsonl(&trace.to_jsonl().unwrap()).unwrap();
+        let mut replay = ReplayBackend::new(trace.clone());
+        let error = Executor::new(&mut replay).run(&program).err().unwrap();
+        assert!(error.message.contains("encoder fault"));
+        assert!(replay.is_finished());
+
+        let other = parse_string("move joint 9 on the left arm to 0\n").unwrap();
+        let mut replay = ReplayBackend::new(trace);
+        let error = Executor::new(&mut replay).run(&other).err().unwrap();
+        assert!(error.message.starts_with("Replay diverged at command 1"));
+    }
+
+    #[test]
+    fn test_debugger_breakpoints_and_stepping() {
+        let program = parse_file(Path::new("../examples/simple.k")).unwrap();
+        let pauses = Rc::new(RefCell::new(Vec::new()));
+        let recorded = pauses.clone();
+        let mut commands = vec![
+            DebugCommand::StepInto,
+            DebugCommand::StepOver,
+            DebugCommand::StepOut,
+            DebugCommand::Abort,
+        ]
+        .into_iter();
+        let debugger = Debugger::new(move |state: &PauseState, _: &mut Vec<Breakpoint>| {
+            let stack = state
+                .call_stack
+                .iter()
+                .map(|frame| format!("{:?}", frame.arguments))
+                .collect::<Vec<String>>()
+                .join(" ");
+            recorded
+                .borrow_mut()
+                .push((state.node.text.clone(), state.reason.clone(), stack));
+            commands.next().unwrap()
+        })
+        .with_breakpoint(Breakpoint::Function("wave [arm] arm".to_string()));
+
+        let mut executor = Executor::new(RecordingBackend::default());
+        executor.set_debugger(debugger);
+        let error = executor.run(&program).err().unwrap();
+        assert!(error.message.starts_with("Execution aborted by debugger"));
+
+        let pauses = pauses.borrow();
+        assert_eq!(
+            pauses[0],
+            (
+                "wave right arm".to_string(),
+                PauseReason::Breakpoint(Breakpoint::Function("wave [arm] arm".to_string())),
+                "[]".to_string()
+            )
+        );
+        assert_eq!(pauses[1].0, "wave joint 1 twice");
+        assert_eq!(pauses[1].2, "[] [(\"arm\", \"right\")]");
+        assert_eq!(pauses[2].0, "wave joint 2 twice");
+        assert_eq!(pauses[3].0, "wave left arm");
+        assert_eq!(executor.backend().commands.len(), 6);
+    }
+
+    #[test]
+    fn test_debugger_prompt() {
+        let program = parse_file(Path::new("../examples/simple.k")).unwrap();
+        let input = std::io::Cursor::new("b 4\nc\nbt\nq\n");
+        let output = SharedOutput::default();
+        let debugger = Debugger::new(PromptHandler::new(input, output.clone())).stop_on_entry();
+        let mut executor = Executor::new(RecordingBackend::default());
+        executor.set_debugger(debugger);
+        assert!(executor.run(&program).is_err());
+
+        let output = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert!(output.contains("Paused (breakpoint on line 4) at 4:9: command"));
+        assert!(output.contains("  #2 wave joint 1 twice [wave joint [joint] twice] (joint=1)"));
+    }
+
+    #[test]
+    fn test_repl_session() {
+        let mut session = Session::default();
+        let definition = "> wave [arm] {\n    raise [arm] arm\n";
+        assert!(is_incomplete(definition));
+        assert!(!is_incomplete(&format!("{}}}\n", definition)));
+        assert_eq!(
+            session
+                .eval("> wave [arm] {\n    raise [arm] arm\n}\n")
+                .unwrap(),
+            "Defined wave [arm]\n"
+        );
+        assert_eq!(
+            session.eval("\" wave [right]\n").unwrap(),
+            "wave right {\n    raise right arm\n}\n"
+        );
+
+        // A definition replaces the old one, and an input that fails keeps none
+        // of its definitions.
+        session
+            .eval("> wave [arm] {\n    lower [arm] arm\n}\n")
+            .unwrap();
+        assert!(session.eval("> nod {\n}\n\" missing\n").is_err());
+        assert_eq!(session.eval(":funcs").unwrap(), "> wave [arm]\n");
+        assert_eq!(
+            session.eval(":expand wave [left]").unwrap(),
+            "wave left {\n    lower left arm\n}\n"
+        );
+
+        session.eval(":load ../examples/simple.k").unwrap();
+        assert_eq!(session.signatures().count(), 3);
+        session.eval(":sim on").unwrap();
+        let output = session.eval("\" wave [left]\n").unwrap();
+        assert_eq!(
+            output,
+            "ran: lower left arm\n1 commands, 1.0s simulated in total\n"
+        );
+        assert!(session.eval(":bogus").is_err());
+    }
+
+    #[test]
+    fn test_watcher_reports_changed_files() {
+        let dir = std::env::temp_dir();
+        let source = dir.join("klang_test_watch.k");
+        let missing = dir.join("klang_test_watch_missing.k");
+        std::fs::write(&source, "\" wave\n").unwrap();
+        let _ = std::fs::remove_file(&missing);
+
+        let mut watcher = Watcher::new([source.clone(), missing.clone(), source.clone()]);
+        assert_eq!(watcher.paths().count(), 2);
+        assert!(watcher.changed().is_empty());
+
+        std::fs::write(&source, "\" wave\n\" wave\n").unwrap();
+        assert_eq!(watcher.changed(), vec![source.clone()]);
+        assert!(watcher.changed().is_empty());
+
+        std::fs::write(&missing, "").unwrap();
+        std::fs::remove_file(&source).unwrap();
+        assert_eq!(watcher.changed(), vec![source.clone(), missing.clone()]);
+
+        std::fs::write(&missing, "\" wave\n").unwrap();
+        watcher.set_paths([missing.clone(), source]);
+        assert_eq!(watcher.paths().count(), 2);
+        assert_eq!(watcher.changed(), vec![missing.clone()]);
+        std::fs::remove_file(&missing).unwrap();
+    }
+
+    #[test]
+    fn test_project_build() {
+        let root = std::env::temp_dir().join("klang_test_project");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("src/moves")).unwrap();
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+        std::fs::write(
+            root.join("klang.toml"),
+            "sources = [\"src\"]\nentry_points = [\"src/main.k\"]\nlibraries = [\"lib\"]\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("src/main.k"), "\" wave both arms\n").unwrap();
+        std::fs::write(
+            root.join("src/moves/both.k"),
+            "> wave both arms {\n    \" wave [right] arm\n    \" wave [left] arm\n}\n",
+        )
+        .unwrap();
+        let library = root.join("lib/arm.k");
+        std::fs::write(&library, "\n> wave [arm] arm {\n    raise [arm] arm\n}\n").unwrap();
+
+        let project = Project::find(&root.join("src/moves")).unwrap().unwrap();
+        let entry = project.entry_points()[0].clone();
+        assert_eq!(project.library_files().unwrap().len(), 2);
+        let mut overlapping = Project::find(&root).unwrap().unwrap();
+        overlapping
+            .manifest
+            .libraries
+            .push(root.join("src/moves/../moves"));
+        assert_eq!(overlapping.library_files().unwrap().len(), 2);
+        let linked = project.link(&entry).unwrap();
+        // Locations are not read back from the message.
+        let message = "Bad (line: 1, column: 1)".to_string();
+        let located = linked.locate(ParseError::at(message, 7, 5));
+        let library_path = project.path(Path::new("lib/arm.k"));
+        assert_eq!(
+            located.message,
+            format!(
+                "Bad (line: 1, column: 1) ({}, line: 3, column: 5)",
+                library_path.display()
+            )
+        );
+
+        let build = || project.build(OutputFormat::Text, false).unwrap();
+        assert!(matches!(build()[0].1, BuildStatus::Compiled));
+        let output = std::fs::read_to_string(root.join("build/src/main.txt")).unwrap();
+        assert!(output.contains("raise right arm"));
+        assert!(matches!(build()[0].1, BuildStatus::UpToDate));
+
+        std::fs::write(&library, "> wave [arm] arm {\n    lower [arm] arm\n}\n").unwrap();
+        assert!(matches!(build()[0].1, BuildStatus::Compiled));
+        std::fs::write(root.join("lib/copy.k"), "> wave [arm] arm {\n}\n").unwrap();
+        match &build()[0].1 {
+            BuildStatus::Failed(e) => assert!(e.message.contains("is defined in both")),
+            status => panic!("Expected the build to fail, got {:?}", status),
+        }
+
+        let manifest = "entry_points = [\"main.k\"]\nrobot = \"robot.toml\"\n";
+        assert!(Project::from_toml(&root, manifest).is_err());
+        let manifest = "entry_points = [\"../main.k\"]\n";
+        assert!(Project::from_toml(&root, manifest).is_err());
+
+        // Entry points with the same name in different directories.
+        std::fs::create_dir_all(root.join("walk")).unwrap();
+        std::fs::create_dir_all(root.join("run")).unwrap();
+        std::fs::write(
+            root.join("klang.toml"),
+            "entry_points = [\"walk/main.k\", \"run/main.k\"]\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("walk/main.k"), "walk\n").unwrap();
+        std::fs::write(root.join("run/main.k"), "run\n").unwrap();
+        let project = Project::find(&root).unwrap().unwrap();
+        for _ in 0..2 {
+            project.build(OutputFormat::Text, false).unwrap();
+            let walk = std::fs::read_to_string(root.join("build/walk/main.txt")).unwrap();
+            let run = std::fs::read_to_string(root.join("build/run/main.txt")).unwrap();
+            assert_eq!((walk.trim(), run.trim()), ("walk", "run"));
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_golden_files() {
+        let program = parse_file(Path::new("../examples/simple.k")).unwrap();
+        let tree = parse_tree_text(&program.to_text()).unwrap();
+        assert_eq!(tree_diff(&tree, &program.program, false), None);
+        assert!(tree_diff(&tree, &program.program, true).is_some());
+
+        let changed = parse_string(
+            "> wave [arm] arm {\n    raise [arm] arm\n}\n\" wave [left] arm\n\" wave [right] arm\n",
+        )
+        .unwrap();
+        let expected = parse_tree_text(
+            "wave left arm {\n  lower left arm\n}\nwave right arm {\n  raise right arm\n}\n",
+        )
+        .unwrap();
+        assert_eq!(
+            tree_diff(&expected, &changed.program, false).unwrap(),
+            [
+                "  wave left arm {",
+                "-     lower left arm",
+                "+     raise left arm",
+                "  }",
+                "  wave right arm { ... }\n",
+            ]
+            .join("\n")
+        );
+
+        let dir = std::env::temp_dir().join("klang_test_golden");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("text.k"), "\" wave\n").unwrap();
+        std::fs::write(dir.join("json.k"), "\" wave\n").unwrap();
+        std::fs::write(dir.join("json.expected.json"), "{}").unwrap();
+        std::fs::write(dir.join("untested.k"), "\" wave\n").unwrap();
+        std::fs::write(
+            dir.join("commented.k"),
+            "// test \"wave\" {\n/*\ntest \"wave\" {\n}\n*/\n",
+        )
+        .unwrap();
+        let tests = GoldenTest::discover(&[dir.clone(), dir.join("text.k")]).unwrap();
+        let formats: Vec<GoldenFormat> = tests.iter().map(|test| test.format).collect();
+        assert_eq!(formats, vec![GoldenFormat::Json, GoldenFormat::Text]);
+
+        let text = &tests[1];
+        assert!(text.check(&changed).is_err());
+        text.bless(&changed).unwrap();
+        assert_eq!(text.check(&changed).unwrap(), None);
+        let json = &tests[0];
+        json.bless(&program).unwrap();
+        assert_eq!(json.check(&program).unwrap(), None);
+        assert!(json.check(&changed).unwrap().is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_test_blocks() {
+        let source = "> wave [arm] arm {\n    move joint 1 on the [arm] arm to 90\n    open the [arm] gripper\n}\n\n\
+                      test \"waves the left arm\" {\n    \" wave [left] arm\n    expect commands {\n        \
+                      move joint 1 on the left arm to 90\n        open the left gripper\n    }\n    \
+                      expect joint \"joint=1 arm=left\" is 90\n    expect gripper \"arm=left\" is open\n    \
+                      expect elapsed is 2\n}\n\ntest \"waits\" {\n    wait 1 seconds\n    expect elapsed is 3\n}\n";
+        assert_eq!(format_string(source).unwrap(), source);
+        assert_eq!(parse_string(source).unwrap().program.len(), 0);
+        assert!(Analysis::new(source).diagnostics.is_empty());
+
+        let schema = PrimitiveSchema::load(Path::new("../examples/primitives.toml")).unwrap();
+        let tests = tests_from_string(source, Some(&schema)).unwrap();
+        let names: Vec<&str> = tests.iter().map(|test| test.name.as_str()).collect();
+        assert_eq!(names, vec!["waves the left arm", "waits"]);
+        assert!(run_test(&tests[0], None).is_ok());
+        let error = run_test(&tests[1], None).err().unwrap();
+        assert_eq!(
+            error.message,
+            "Expected 3s to have elapsed, but 1s did (line: 19, column: 5)"
+        );
+
+        let error = tests_from_string("test \"x\" {\n    expect joint is 90\n}\n", None).err();
+        assert!(error
+            .unwrap()
+            .message
+            .starts_with("Expected the joint to check"));
+    }
 }
@@ -0,0 +1,386 @@
+use crate::executor::errors::ExecutionError;
+use crate::executor::sim::SimBackend;
+use crate::executor::Executor;
+use crate::parser::cst::{SyntaxKind, SyntaxTree};
+use crate::parser::errors::ParseError;
+use crate::parser::robot::RobotDescription;
+use crate::parser::structs::{Expectation, KlangProgram, Node, SourceLocation, TestCase, TestStep};
+use crate::project::find_sources;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GoldenFormat {
+    /// The indented tree written by `kompile build --format text`.
+    Text,
+    /// The program as JSON, which also records source locations, called
+    /// functions, bound arguments and lowered actions.
+    Json,
+}
+
+/// A source file and the expanded program it is expected to compile to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GoldenTest {
+    pub source: PathBuf,
+    pub golden: PathBuf,
+    pub format: GoldenFormat,
+}
+
+impl GoldenTest {
+    /// The test of a source file, against `name.expected.json` if there is
+    /// one, or else `name.expected`.
+    pub fn new(source: &Path) -> Self {
+        let json = source.with_extension("expected.json");
+        let (golden, format) = if json.is_file() {
+            (json, GoldenFormat::Json)
+        } else {
+            (source.with_extension("expected"), GoldenFormat::Text)
+        };
+        GoldenTest {
+            source: source.to_path_buf(),
+            golden,
+            format,
+        }
+    }
+
+    /// The tests of the given source files, and of the source files in the
+    /// given directories and below that have a golden file or test blocks.
+    pub fn discover(paths: &[PathBuf]) -> Result<Vec<GoldenTest>, ParseError> {
+        let mut tests = Vec::new();
+        for path in paths {
+            if path.is_dir() {
+                let mut sources = Vec::new();
+                find_sources(path, &mut sources)?;
+                tests.extend(
+                    sources
+                        .iter()
+                        .map(|source| GoldenTest::new(source))
+                        .filter(|test| test.golden.is_file() || has_test_blocks(&test.source)),
+                );
+            } else {
+                tests.push(GoldenTest::new(path));
+            }
+        }
+        Ok(tests)
+    }
+
+    pub fn expected(&self) -> Result<Vec<Node>, ParseError> {
+        let golden = fs::read_to_string(&self.golden).map_err(|_| {
+            ParseError::new(format!(
+                "Missing golden file '{}', run with --bless to create it",
+                self.golden.display()
+            ))
+        })?;
+        match self.format {
+            GoldenFormat::Text => parse_tree_text(&golden),
+            GoldenFormat::Json => Ok(KlangProgram::from_json(&golden)?.program),
+        }
+    }
+
+    /// The differences between the golden file and the program, or `None`
+    /// if they match.
+    pub fn check(&self, program: &KlangProgram) -> Result<Option<String>, ParseError> {
+        let details = self.format == GoldenFormat::Json;
+        Ok(tree_diff(&self.expected()?, &program.program, details))
+    }
+
+    /// Makes the program the expected output.
+    pub fn bless(&self, program: &KlangProgram) -> Result<(), ParseError> {
+        match self.format {
+            GoldenFormat::Text => program.save_text(&self.golden),
+            GoldenFormat::Json => program.save_json(&self.golden),
+        }
+    }
+}
+
+/// Whether the source file has `test "name" { ... }` blocks. Files that do
+/// not parse have none.
+pub fn has_test_blocks(source: &Path) -> bool {
+    let Ok(source) = fs::read_to_string(source) else {
+        return false;
+    };
+    SyntaxTree::parse(&source).is_ok_and(|tree| {
+        tree.root
+            .nodes()
+            .any(|node| node.kind == SyntaxKind::TestBlock)
+    })
+}
+
+/// How far a joint position or the elapsed time may be from the expected
+/// value.
+const TOLERANCE: f64 = 1e-6;
+
+/// Runs a test on a freshly reset simulated robot, stopping at the first
+/// expectation that does not hold.
+pub fn run_test(test: &TestCase, robot: Option<&RobotDescription>) -> Result<(), ExecutionError> {
+    let mut executor = Executor::new(SimBackend::default());
+    if let Some(robot) = robot {
+        executor.set_robot(robot.clone());
+    }
+    // Commands before this have been checked by an earlier expectation.
+    let mut checked = 0;
+    for step in &test.steps {
+        let (expectation, location) = match step {
+            TestStep::Run(program) => {
+                executor.run(&KlangProgram {
+                    program: program.clone(),
+                })?;
+                continue;
+            }
+            TestStep::Expect {
+                expectation,
+                location,
+            } => (expectation, location),
+        };
+        let sim = executor.backend();
+        let failure = match expectation {
+            Expectation::Commands(commands) => {
+                // Spacing inside a command does not matter.
+                let command =
+                    |text: &String| leaf(&text.split_whitespace().collect::<Vec<_>>().join(" "));
+                let expected: Vec<Node> = commands.iter().map(command).collect();
+                let actual: Vec<Node> = sim.history()[checked..].iter().map(command).collect();
+                checked = sim.history().len();
+                tree_diff(&expected, &actual, false).map(|diff| {
+                    format!(
+                        "Expected other commands{}\n{}",
+                        suffix(location),
+                        diff.trim_end()
+                    )
+                })
+            }
+            Expectation::Joint { key, position } => match sim.joint_position(key) {
+                Some(actual) if (actual - position).abs() <= TOLERANCE => None,
+                Some(actual) => Some(format!(
+                    "Expected joint '{}' at {}, but it is at {}{}",
+                    key,
+                    position,
+                    actual,
+                    suffix(location)
+                )),
+                None => Some(format!(
+                    "Expected joint '{}' at {}, but it has not moved{}",
+                    key,
+                    position,
+                    suffix(location)
+                )),
+            },
+            Expectation::Gripper { key, open } => {
+                let state = |open: bool| if open { "open" } else { "closed" };
+                match sim.grippers().get(key) {
+                    Some(actual) if actual == open => None,
+                    actual => Some(format!(
+                        "Expected gripper '{}' to be {}, but it is {}{}",
+                        key,
+                        state(*open),
+                        actual.map_or("not set", |actual| state(*actual)),
+                        suffix(location)
+                    )),
+                }
+            }
+            Expectation::Elapsed(seconds) if (sim.elapsed() - seconds).abs() <= TOLERANCE => None,
+            Expectation::Elapsed(seconds) => Some(format!(
+                "Expected {}s to have elapsed, but {}s did{}",
+                seconds,
+                sim.elapsed(),
+                suffix(location)
+            )),
+        };
+        if let Some(message) = failure {
+            return Err(ExecutionError::new(message));
+        }
+    }
+    Ok(())
+}
+
+fn suffix(location: &Option<SourceLocation>) -> String {
+    match location {
+        Some(location) => format!(" (line: {}, column: {})", location.line, location.column),
+        None => String::new(),
+    }
+}
+
+fn leaf(text: &str) -> Node {
+    Node {
+        text: text.to_string(),
+        children: Vec::new(),
+        location: None,
+        function: None,
+        action: None,
+        arguments: Vec::new(),
+    }
+}
+
+/// Reads back the tree written by `KlangProgram::to_text`.
+pub fn parse_tree_text(text: &str) -> Result<Vec<Node>, ParseError> {
+    let mut roots = Vec::new();
+    let mut open: Vec<Node> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let node = if line.is_empty() {
+            continue;
+        } else if line == "}" {
+            open.pop()
+                .ok_or_else(|| ParseError::at("Unexpected '}'".to_string(), index + 1, 1))?
+        } else if let Some(text) = line.strip_suffix(" {") {
+            open.push(leaf(text));
+            continue;
+        } else {
+            leaf(line)
+        };
+        match open.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => roots.push(node),
+        }
+    }
+    match open.last() {
+        Some(node) => Err(ParseError::new(format!(
+            "Missing '}}' after '{}'",
+            node.text
+        ))),
+        None => Ok(roots),
+    }
+}
+
+/// Whether two nodes are the same, apart from their children. With
+/// `details`, everything recorded about them must match, not just the text.
+fn same_node(expected: &Node, actual: &Node, details: bool) -> bool {
+    expected.text == actual.text
+        && (!details
+            || (expected.location == actual.location
+                && expected.function == actual.function
+                && expected.action == actual.action
+                && expected.arguments == actual.arguments))
+}
+
+fn same_tree(expected: &Node, actual: &Node, details: bool) -> bool {
+    same_node(expected, actual, details)
+        && expected.children.len() == actual.children.len()
+        && expected
+            .children
+            .iter()
+            .zip(&actual.children)
+            .all(|(expected, actual)| same_tree(expected, actual, details))
+}
+
+/// The differences between two trees, with the lines of the expected tree
+/// marked `-` and those of the actual one `+`. Subtrees that match are
+/// shown collapsed around the changes. Returns `None` if the trees match.
+pub fn tree_diff(expected: &[Node], actual: &[Node], details: bool) -> Option<String> {
+    let same = expected.len() == actual.len()
+        && expected
+            .iter()
+            .zip(actual)
+            .all(|(expected, actual)| same_tree(expected, actual, details));
+    if same {
+        return None;
+    }
+    let mut diff = String::new();
+    diff_nodes(expected, actual, 0, details, &mut diff);
+    Some(diff)
+}
+
+/// Lines up the nodes by text, as the longest common subsequence.
+fn diff_nodes(expected: &[Node], actual: &[Node], depth: usize, details: bool, diff: &mut String) {
+    let (n, m) = (expected.len(), actual.len());
+    let mut common = vec![vec![0; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            common[i][j] = if expected[i].text == actual[j].text {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && expected[i].text == actual[j].text {
+            diff_node(&expected[i], &actual[j], depth, details, diff);
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || common[i + 1][j] >= common[i][j + 1]) {
+            write_tree('-', &expected[i], depth, diff);
+            i += 1;
+        } else {
+            write_tree('+', &actual[j], depth, diff);
+            j += 1;
+        }
+    }
+}
+
+fn diff_node(expected: &Node, actual: &Node, depth: usize, details: bool, diff: &mut String) {
+    let indent = "    ".repeat(depth);
+    if same_tree(expected, actual, details) {
+        let collapsed = if expected.children.is_empty() {
+            ""
+        } else {
+            " { ... }"
+        };
+        diff.push_str(&format!("  {}{}{}\n", indent, expected.text, collapsed));
+        return;
+    }
+    let has_children = !expected.children.is_empty() || !actual.children.is_empty();
+    let open = if has_children { " {" } else { "" };
+    if same_node(expected, actual, details) {
+        diff.push_str(&format!("  {}{}{}\n", indent, expected.text, open));
+    } else {
+        diff.push_str(&format!("- {}{}{}\n", indent, describe(expected), open));
+        diff.push_str(&format!("+ {}{}{}\n", indent, describe(actual), open));
+    }
+    if has_children {
+        diff_nodes(
+            &expected.children,
+            &actual.children,
+            depth + 1,
+            details,
+            diff,
+        );
+        diff.push_str(&format!("  {}}}\n", indent));
+    }
+}
+
+/// The text of the node with everything else recorded about it.
+fn describe(node: &Node) -> String {
+    let mut details = Vec::new();
+    if let Some(location) = node.location {
+        details.push(format!("at {}", location));
+    }
+    if let Some(function) = &node.function {
+        details.push(format!("calls `{}`", function));
+    }
+    if !node.arguments.is_empty() {
+        let arguments: Vec<String> = node
+            .arguments
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        details.push(format!("with {}", arguments.join(" ")));
+    }
+    if let Some(action) = &node.action {
+        let arguments: Vec<String> = action
+            .arguments
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        details.push(format!("action {}({})", action.name, arguments.join(", ")));
+    }
+    if details.is_empty() {
+        node.text.clone()
+    } else {
+        format!("{}  ({})", node.text, details.join(", "))
+    }
+}
+
+fn write_tree(sign: char, node: &Node, depth: usize, diff: &mut String) {
+    let indent = "    ".repeat(depth);
+    if node.children.is_empty() {
+        diff.push_str(&format!("{} {}{}\n", sign, indent, node.text));
+        return;
+    }
+    diff.push_str(&format!("{} {}{} {{\n", sign, indent, node.text));
+    for child in &node.children {
+        write_tree(sign, child, depth + 1, diff);
+    }
+    diff.push_str(&format!("{} {}}}\n", sign, indent));
+}
@@ -0,0 +1,97 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// What a file looked like when it was last checked. A missing file has no
+/// stamp, so deleting and recreating a file are both changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Stamp {
+    modified: SystemTime,
+    len: u64,
+}
+
+fn stamp(path: &Path) -> Option<Stamp> {
+    let metadata = fs::metadata(path).ok()?;
+    Some(Stamp {
+        modified: metadata.modified().ok()?,
+        len: metadata.len(),
+    })
+}
+
+/// Polls a set of files for changes on disk.
+pub struct Watcher {
+    files: Vec<(PathBuf, Option<Stamp>)>,
+    pub interval: Duration,
+}
+
+impl Watcher {
+    pub fn new(paths: impl IntoIterator<Item = PathBuf>) -> Self {
+        let mut files: Vec<(PathBuf, Option<Stamp>)> = Vec::new();
+        for path in paths {
+            if !files.iter().any(|(watched, _)| *watched == path) {
+                let stamp = stamp(&path);
+                files.push((path, stamp));
+            }
+        }
+        Watcher {
+            files,
+            interval: Duration::from_millis(250),
+        }
+    }
+
+    /// Watches a new set of files. Files that were already watched keep
+    /// their stamps, so changes made since they were taken are not lost.
+    pub fn set_paths(&mut self, paths: impl IntoIterator<Item = PathBuf>) {
+        let mut old = std::mem::take(&mut self.files);
+        for path in paths {
+            if self.files.iter().any(|(watched, _)| *watched == path) {
+                continue;
+            }
+            let stamp = match old.iter().position(|(watched, _)| *watched == path) {
+                Some(index) => old.swap_remove(index).1,
+                None => stamp(&path),
+            };
+            self.files.push((path, stamp));
+        }
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files.iter().map(|(path, _)| path.as_path())
+    }
+
+    /// The files that changed since the last check.
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        for (path, old) in &mut self.files {
+            let new = stamp(path);
+            if new != *old {
+                *old = new;
+                changed.push(path.clone());
+            }
+        }
+        changed
+    }
+
+    /// Blocks until some files change, then waits until they stop changing
+    /// so that a save in progress is not picked up half written.
+    pub fn wait(&mut self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        while changed.is_empty() {
+            thread::sleep(self.interval);
+            changed = self.changed();
+        }
+        loop {
+            thread::sleep(self.interval);
+            let more = self.changed();
+            if more.is_empty() {
+                return changed;
+            }
+            for path in more {
+                if !changed.contains(&path) {
+                    changed.push(path);
+                }
+            }
+        }
+    }
+}
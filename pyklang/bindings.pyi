@@ -1,14 +1,45 @@
 # This file is automatically generated by pyo3_stub_gen
 # ruff: noqa: E501, F401
 
+import typing
+
+
+class PyCallGraph:
+    def functions(self) -> list[str]:
+        ...
+
+    def edges(self) -> list[tuple[str, str, int, list[str]]]:
+        ...
+
+    def to_dot(self) -> str:
+        ...
+
+    def to_mermaid(self) -> str:
+        ...
+
 
 class PyKlangProgram:
     def save_binary(self, path:str) -> None:
         ...
 
+    def save_binary_deduplicated(self, path:str) -> None:
+        ...
+
     def save_text(self, path:str) -> None:
         ...
 
+    def save_json(self, path:str) -> None:
+        ...
+
+    def save_yaml(self, path:str) -> None:
+        ...
+
+    def to_json(self) -> str:
+        ...
+
+    def to_yaml(self) -> str:
+        ...
+
     def __repr__(self) -> str:
         ...
 
@@ -16,16 +47,76 @@ class PyKlangProgram:
     def load_binary(path:str) -> PyKlangProgram:
         ...
 
+    @staticmethod
+    def load_json(path:str) -> PyKlangProgram:
+        ...
+
+    @staticmethod
+    def load_yaml(path:str) -> PyKlangProgram:
+        ...
+
+    @staticmethod
+    def from_json(input:str) -> PyKlangProgram:
+        ...
+
+    @staticmethod
+    def from_yaml(input:str) -> PyKlangProgram:
+        ...
+
     def to_list(self) -> list[list[str]]:
         ...
 
+    def to_dot(self) -> str:
+        ...
+
+    def to_mermaid(self) -> str:
+        ...
+
+    def to_html(self) -> str:
+        ...
+
+
+class PySimBackend:
+    def __new__(cls,default_duration:typing.Optional[float]=None, durations:typing.Optional[typing.Mapping[str, float]]=None, joint_velocity:typing.Optional[float]=None): ...
+    def inject_failure(self, pattern:str, message:str) -> None:
+        ...
+
+    def inject_failure_at(self, index:int, message:str) -> None:
+        ...
+
+    def run(self, program:PyKlangProgram, trace:typing.Optional[str]=None) -> int:
+        r"""
+        Runs the program, returning the number of commands executed. If
+        `trace` is given, the executed commands are written there as JSON lines,
+        even if the run fails.
+        """
+        ...
+
+    def joints(self) -> dict[str, float]:
+        ...
+
+    def grippers(self) -> dict[str, bool]:
+        ...
+
+    def elapsed(self) -> float:
+        ...
+
+    def history(self) -> list[str]:
+        ...
+
 
 def get_version() -> str:
     ...
 
-def parse_file(path:str) -> PyKlangProgram:
+def parse_file(path:str, schema:typing.Optional[str]=None) -> PyKlangProgram:
+    ...
+
+def parse_string(input:str, schema:typing.Optional[str]=None) -> PyKlangProgram:
+    ...
+
+def call_graph_file(path:str) -> PyCallGraph:
     ...
 
-def parse_string(input:str) -> PyKlangProgram:
+def call_graph_string(input:str) -> PyCallGraph:
     ...
 
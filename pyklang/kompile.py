@@ -4,7 +4,7 @@ from pathlib import Path
 
 import click
 
-from pyklang.bindings import parse_file
+from pyklang.bindings import call_graph_file, parse_file
 
 
 @click.command()
@@ -12,9 +12,64 @@ from pyklang.bindings import parse_file
 @click.option("-o", "--output", help="The output file to compile.")
 @click.option("-i", "--inplace", is_flag=True, help="Overwrite the input file.")
 @click.option("-t", "--text", is_flag=True, help="Output the text representation of the program.")
-def main(input_file: str, output: str | None, inplace: bool, text: bool) -> None:
+@click.option(
+    "-f",
+    "--format",
+    "output_format",
+    type=click.Choice(["binary", "text", "json", "yaml"]),
+    help="The output format. Defaults to binary, or text if -t is given.",
+)
+@click.option(
+    "-d",
+    "--deduplicate",
+    is_flag=True,
+    help="Share repeated command subtrees in the output, which must be in the binary format.",
+)
+@click.option("-s", "--schema", help="A primitive schema to validate commands against.")
+@click.option(
+    "-g",
+    "--call-graph",
+    type=click.Choice(["dot", "mermaid"]),
+    help="Output the call graph of the program instead of compiling it.",
+)
+@click.option(
+    "--tree",
+    type=click.Choice(["dot", "mermaid", "html"]),
+    help="Output a visualization of the expanded program tree instead of compiling it.",
+)
+def main(
+    input_file: str,
+    output: str | None,
+    inplace: bool,
+    text: bool,
+    output_format: str | None,
+    deduplicate: bool,
+    schema: str | None,
+    call_graph: str | None,
+    tree: str | None,
+) -> None:
     """Kompile a Klang program."""
-    program = parse_file(input_file)
+    if deduplicate and (output_format or ("text" if text else "binary")) != "binary":
+        raise click.UsageError("--deduplicate only applies to the binary format")
+
+    if call_graph is not None:
+        graph = call_graph_file(input_file)
+        rendered = graph.to_dot() if call_graph == "dot" else graph.to_mermaid()
+        if output is None:
+            click.echo(rendered, nl=False)
+        else:
+            Path(output).write_text(rendered)
+        return
+
+    program = parse_file(input_file, schema)
+
+    if tree is not None:
+        rendered = {"dot": program.to_dot, "mermaid": program.to_mermaid, "html": program.to_html}[tree]()
+        if output is None:
+            click.echo(rendered, nl=False)
+        else:
+            Path(output).write_text(rendered)
+        return
 
     if inplace:
         if output is not None:
@@ -25,8 +80,16 @@ def main(input_file: str, output: str | None, inplace: bool, text: bool) -> None
         click.echo(program)
     else:
         Path(output).parent.mkdir(parents=True, exist_ok=True)
-        if text:
+        if output_format is None:
+            output_format = "text" if text else "binary"
+        if output_format == "text":
             program.save_text(output)
+        elif output_format == "json":
+            program.save_json(output)
+        elif output_format == "yaml":
+            program.save_yaml(output)
+        elif deduplicate:
+            program.save_binary_deduplicated(output)
         else:
             program.save_binary(output)
 
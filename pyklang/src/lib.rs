@@ -1,10 +1,19 @@
+use klang::executor::sim::{SimBackend, SimConfig};
+use klang::executor::Executor;
 use klang::parser::errors::ParseError;
+use klang::parser::passes::call_graph::CallGraph;
+use klang::parser::schema::PrimitiveSchema;
 use klang::parser::structs::KlangProgram;
-use klang::parser::{parse_file as klang_parse_file, parse_string as klang_parse_string};
+use klang::parser::{
+    call_graph_from_file as klang_call_graph_from_file,
+    call_graph_from_string as klang_call_graph_from_string,
+    parse_file_with_schema as klang_parse_file, parse_string_with_schema as klang_parse_string,
+};
 use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
 use pyo3_stub_gen::define_stub_info_gatherer;
 use pyo3_stub_gen::derive::*;
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
 
 #[pyfunction]
@@ -13,22 +22,49 @@ fn get_version() -> String {
     env!("CARGO_PKG_VERSION").to_string()
 }
 
+fn load_schema(schema: Option<&str>) -> PyResult<Option<PrimitiveSchema>> {
+    schema
+        .map(|schema| PrimitiveSchema::load(Path::new(schema)))
+        .transpose()
+        .map_err(|e| PyValueError::new_err(PyParseError { inner: e }.to_string()))
+}
+
 #[pyfunction]
 #[gen_stub_pyfunction]
-fn parse_file(path: &str) -> PyResult<PyKlangProgram> {
-    let program = klang_parse_file(Path::new(path))
+#[pyo3(signature = (path, schema=None))]
+fn parse_file(path: &str, schema: Option<&str>) -> PyResult<PyKlangProgram> {
+    let schema = load_schema(schema)?;
+    let program = klang_parse_file(Path::new(path), schema.as_ref())
         .map_err(|e| PyValueError::new_err(PyParseError { inner: e }.to_string()))?;
     Ok(PyKlangProgram { inner: program })
 }
 
 #[pyfunction]
 #[gen_stub_pyfunction]
-fn parse_string(input: &str) -> PyResult<PyKlangProgram> {
-    let program = klang_parse_string(input)
+#[pyo3(signature = (input, schema=None))]
+fn parse_string(input: &str, schema: Option<&str>) -> PyResult<PyKlangProgram> {
+    let schema = load_schema(schema)?;
+    let program = klang_parse_string(input, schema.as_ref())
         .map_err(|e| PyValueError::new_err(PyParseError { inner: e }.to_string()))?;
     Ok(PyKlangProgram { inner: program })
 }
 
+#[pyfunction]
+#[gen_stub_pyfunction]
+fn call_graph_file(path: &str) -> PyResult<PyCallGraph> {
+    let graph = klang_call_graph_from_file(Path::new(path))
+        .map_err(|e| PyValueError::new_err(PyParseError { inner: e }.to_string()))?;
+    Ok(PyCallGraph { inner: graph })
+}
+
+#[pyfunction]
+#[gen_stub_pyfunction]
+fn call_graph_string(input: &str) -> PyResult<PyCallGraph> {
+    let graph = klang_call_graph_from_string(input)
+        .map_err(|e| PyValueError::new_err(PyParseError { inner: e }.to_string()))?;
+    Ok(PyCallGraph { inner: graph })
+}
+
 #[gen_stub_pyclass]
 #[pyclass]
 struct PyKlangProgram {
@@ -50,12 +86,42 @@ impl PyKlangProgram {
             .map_err(|e| PyValueError::new_err(e.to_string()))
     }
 
+    fn save_binary_deduplicated(&self, path: &str) -> PyResult<()> {
+        self.inner
+            .save_binary_deduplicated(Path::new(path))
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
     fn save_text(&self, path: &str) -> PyResult<()> {
         self.inner
             .save_text(Path::new(path))
             .map_err(|e| PyValueError::new_err(e.to_string()))
     }
 
+    fn save_json(&self, path: &str) -> PyResult<()> {
+        self.inner
+            .save_json(Path::new(path))
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    fn save_yaml(&self, path: &str) -> PyResult<()> {
+        self.inner
+            .save_yaml(Path::new(path))
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    fn to_json(&self) -> PyResult<String> {
+        self.inner
+            .to_json()
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    fn to_yaml(&self) -> PyResult<String> {
+        self.inner
+            .to_yaml()
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
     fn __repr__(&self) -> PyResult<String> {
         Ok(self.inner.to_string())
     }
@@ -67,9 +133,156 @@ impl PyKlangProgram {
         Ok(PyKlangProgram { inner: program })
     }
 
+    #[staticmethod]
+    fn load_json(path: &str) -> PyResult<PyKlangProgram> {
+        let program = KlangProgram::load_json(Path::new(path))
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(PyKlangProgram { inner: program })
+    }
+
+    #[staticmethod]
+    fn load_yaml(path: &str) -> PyResult<PyKlangProgram> {
+        let program = KlangProgram::load_yaml(Path::new(path))
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(PyKlangProgram { inner: program })
+    }
+
+    #[staticmethod]
+    fn from_json(input: &str) -> PyResult<PyKlangProgram> {
+        let program =
+            KlangProgram::from_json(input).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(PyKlangProgram { inner: program })
+    }
+
+    #[staticmethod]
+    fn from_yaml(input: &str) -> PyResult<PyKlangProgram> {
+        let program =
+            KlangProgram::from_yaml(input).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(PyKlangProgram { inner: program })
+    }
+
     fn to_list(&self) -> PyResult<Vec<Vec<String>>> {
         Ok(self.inner.to_list().clone())
     }
+
+    fn to_dot(&self) -> String {
+        self.inner.to_dot()
+    }
+
+    fn to_mermaid(&self) -> String {
+        self.inner.to_mermaid()
+    }
+
+    fn to_html(&self) -> String {
+        self.inner.to_html()
+    }
+}
+
+#[gen_stub_pyclass]
+#[pyclass]
+struct PyCallGraph {
+    inner: CallGraph,
+}
+
+#[gen_stub_pymethods]
+#[pymethods]
+impl PyCallGraph {
+    fn functions(&self) -> Vec<String> {
+        self.inner.functions.clone()
+    }
+
+    fn edges(&self) -> Vec<(String, String, usize, Vec<String>)> {
+        self.inner
+            .edges
+            .iter()
+            .map(|edge| {
+                (
+                    edge.caller.clone(),
+                    edge.callee.clone(),
+                    edge.count,
+                    edge.bindings.clone(),
+                )
+            })
+            .collect()
+    }
+
+    fn to_dot(&self) -> String {
+        self.inner.to_dot()
+    }
+
+    fn to_mermaid(&self) -> String {
+        self.inner.to_mermaid()
+    }
+}
+
+#[gen_stub_pyclass]
+#[pyclass]
+struct PySimBackend {
+    inner: SimBackend,
+}
+
+#[gen_stub_pymethods]
+#[pymethods]
+impl PySimBackend {
+    #[new]
+    #[pyo3(signature = (default_duration=None, durations=None, joint_velocity=None))]
+    fn new(
+        default_duration: Option<f64>,
+        durations: Option<HashMap<String, f64>>,
+        joint_velocity: Option<f64>,
+    ) -> Self {
+        let defaults = SimConfig::default();
+        PySimBackend {
+            inner: SimBackend::new(SimConfig {
+                default_duration: default_duration.unwrap_or(defaults.default_duration),
+                durations: durations.unwrap_or(defaults.durations),
+                joint_velocity,
+            }),
+        }
+    }
+
+    fn inject_failure(&mut self, pattern: &str, message: &str) {
+        self.inner.inject_failure(pattern, message);
+    }
+
+    fn inject_failure_at(&mut self, index: usize, message: &str) {
+        self.inner.inject_failure_at(index, message);
+    }
+
+    /// Runs the program, returning the number of commands executed. If
+    /// `trace` is given, the executed commands are written there as JSON lines,
+    /// even if the run fails.
+    #[pyo3(signature = (program, trace=None))]
+    fn run(&mut self, program: &PyKlangProgram, trace: Option<&str>) -> PyResult<usize> {
+        let mut executor = Executor::new(&mut self.inner);
+        if trace.is_some() {
+            executor.record_trace();
+        }
+        let result = executor.run(&program.inner);
+        if let (Some(path), Some(recorded)) = (trace, executor.take_trace()) {
+            recorded
+                .save(Path::new(path))
+                .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        }
+        let summary = result.map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(summary.commands)
+    }
+
+    fn joints(&self) -> BTreeMap<String, f64> {
+        self.inner.joints().clone()
+    }
+
+    fn grippers(&self) -> BTreeMap<String, bool> {
+        self.inner.grippers().clone()
+    }
+
+    fn elapsed(&self) -> f64 {
+        self.inner.elapsed()
+    }
+
+    fn history(&self) -> Vec<String> {
+        self.inner.history().to_vec()
+    }
 }
 
 #[pyclass]
@@ -95,6 +308,9 @@ fn bindings(m: &Bound<PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(get_version, m)?)?;
     m.add_function(wrap_pyfunction!(parse_file, m)?)?;
     m.add_function(wrap_pyfunction!(parse_string, m)?)?;
+    m.add_function(wrap_pyfunction!(call_graph_file, m)?)?;
+    m.add_function(wrap_pyfunction!(call_graph_string, m)?)?;
+    m.add_class::<PySimBackend>()?;
     m.add_class::<PyParseError>()?;
     Ok(())
 }